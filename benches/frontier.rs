@@ -0,0 +1,79 @@
+// frontier.rs
+//! Times the corruption frontier at the size of a heavily corrupted stage.
+//! Run with `cargo bench --bench frontier`.
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// Without the test harness the module's tests are left out, and with them
+// what their imports are for
+#[path = "../src/tiles/frontier.rs"]
+#[allow(dead_code, unused_imports)]
+mod frontier;
+
+use frontier::PotentiallyCorruptedTiles;
+
+/// Sides of the square of frontier tiles benched, up to a million tiles.
+const SIDES: [i32; 3] = [100, 300, 1000];
+
+fn square(side: i32) -> PotentiallyCorruptedTiles {
+    let mut frontier = PotentiallyCorruptedTiles::default();
+    for x in 0..side {
+        for y in 0..side {
+            frontier.insert((x, y));
+        }
+    }
+    frontier
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frontier_insert");
+    for side in SIDES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(side * side),
+            &side,
+            |b, &side| b.iter(|| square(black_box(side))),
+        );
+    }
+    group.finish();
+}
+
+fn remove(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frontier_remove");
+    for side in SIDES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(side * side),
+            &side,
+            |b, &side| {
+                // Every other tile, so each removal moves another into its slot
+                b.iter_batched_ref(
+                    || square(side),
+                    |frontier| {
+                        for x in 0..side {
+                            for y in (0..side).step_by(2) {
+                                frontier.remove(black_box((x, y)));
+                            }
+                        }
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn sample(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frontier_sample");
+    for side in SIDES {
+        let frontier = square(side);
+        let mut rng = StdRng::seed_from_u64(1);
+        group.bench_with_input(BenchmarkId::from_parameter(side * side), &side, |b, _| {
+            b.iter(|| frontier.sample(&mut rng))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert, remove, sample);
+criterion_main!(benches);
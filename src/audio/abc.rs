@@ -0,0 +1,737 @@
+// abc.rs
+use super::{
+    letter_index, KeySignature, Song, SongTimingError, Step, TempoChange, BPM, LETTER_SEMITONES,
+};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::utils::BoxedFuture;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum AbcError {
+    #[error("could not read ABC file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("ABC file is not valid UTF-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error("ABC tune has no K: field")]
+    MissingKey,
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("line {line}: pitch {pitch} is outside the MIDI range")]
+    PitchOutOfRange { line: usize, pitch: i32 },
+    #[error("invalid tune timing: {0}")]
+    Timing(#[from] SongTimingError),
+}
+
+/// Parses the first tune of an ABC file into a `Song`.
+///
+/// Supports key signatures (including modes and `octave=`/`transpose=`), unit and
+/// per-note lengths, broken rhythms, triplets, rests, ties, chords, and `|: :|`
+/// repeats with first and second endings. Voice overlays are rejected.
+pub fn song_from_abc(source: &str) -> Result<Song, AbcError> {
+    let mut parser = AbcParser::default();
+    let mut in_body = false;
+
+    for (number, raw_line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = raw_line.split('%').next().unwrap_or("").trim_end();
+
+        if !in_body {
+            if let Some((field, value)) = field_line(line) {
+                parser.header_field(field, value)?;
+                in_body = field == 'K';
+            }
+            continue;
+        }
+
+        // A blank line ends the tune
+        if raw_line.trim().is_empty() {
+            break;
+        }
+
+        match field_line(line) {
+            Some(('V', value)) => parser.voice(value),
+            Some((field, value)) => parser.body_field(field, value)?,
+            None if parser.skip_voice => {}
+            None => parser.body_line(line)?,
+        }
+    }
+
+    if !in_body {
+        return Err(AbcError::MissingKey);
+    }
+
+    let song = parser.finish();
+    song.check_timing()?;
+    Ok(song)
+}
+
+/// Splits `X:value` style field lines into their letter and value.
+fn field_line(line: &str) -> Option<(char, &str)> {
+    let mut chars = line.chars();
+    let field = chars.next()?;
+
+    if field.is_ascii_alphabetic() && chars.next() == Some(':') {
+        Some((field, line[2..].trim()))
+    } else {
+        None
+    }
+}
+
+/// Parses a fraction such as `1/8` or a whole number.
+fn fraction(value: &str) -> Option<f32> {
+    match value.trim().split_once('/') {
+        Some((num, den)) => Some(num.trim().parse::<f32>().ok()? / den.trim().parse::<f32>().ok()?),
+        None => value.trim().parse().ok(),
+    }
+}
+
+/// Key signature accidentals per letter, plus any octave shift and transposition.
+#[derive(Clone, Default)]
+struct Key {
+    accidentals: [i32; 7],
+    transpose: i32,
+}
+
+impl Key {
+    fn parse(value: &str) -> Option<Key> {
+        let mut key = Key::default();
+        let mut tokens = value.split_whitespace().peekable();
+
+        let Some(tonic) = tokens.next() else {
+            return Some(key);
+        };
+        if tonic.eq_ignore_ascii_case("none") || tonic == "HP" || tonic == "Hp" {
+            return Some(key);
+        }
+
+        let mut chars = tonic.chars();
+        let mut fifths = match chars.next()? {
+            'F' => -1,
+            'C' => 0,
+            'G' => 1,
+            'D' => 2,
+            'A' => 3,
+            'E' => 4,
+            'B' => 5,
+            _ => return None,
+        };
+        let mut mode = chars.as_str();
+        if let Some(rest) = mode.strip_prefix('#') {
+            fifths += 7;
+            mode = rest;
+        } else if let Some(rest) = mode.strip_prefix('b') {
+            fifths -= 7;
+            mode = rest;
+        }
+        if mode.is_empty() {
+            if let Some(next) = tokens.peek() {
+                if mode_offset(next).is_some() {
+                    mode = tokens.next().unwrap_or_default();
+                }
+            }
+        }
+        fifths += mode_offset(mode)?;
+
+        key.accidentals = KeySignature::new(fifths).accidentals();
+
+        for token in tokens {
+            if let Some(octave) = token.strip_prefix("octave=") {
+                key.transpose += octave.parse::<i32>().ok()? * 12;
+            } else if let Some(semitones) = token.strip_prefix("transpose=") {
+                key.transpose += semitones.parse::<i32>().ok()?;
+            } else if let Some((accidental, letter)) = explicit_accidental(token) {
+                key.accidentals[letter] = accidental;
+            }
+        }
+
+        Some(key)
+    }
+}
+
+/// Fifths to add to a major key's signature for the given mode name.
+fn mode_offset(mode: &str) -> Option<i32> {
+    let mode = mode.to_ascii_lowercase();
+    match mode.as_str() {
+        "" => return Some(0),
+        "m" => return Some(-3),
+        _ => {}
+    }
+
+    match mode.get(..3)? {
+        "maj" | "ion" => Some(0),
+        "mix" => Some(-1),
+        "dor" => Some(-2),
+        "min" | "aeo" => Some(-3),
+        "phr" => Some(-4),
+        "loc" => Some(-5),
+        "lyd" => Some(1),
+        _ => None,
+    }
+}
+
+/// Parses key signature overrides such as `^f` or `_B`.
+fn explicit_accidental(token: &str) -> Option<(i32, usize)> {
+    let (accidental, letter) = match token.as_bytes() {
+        [b'^', b'^', letter] => (2, *letter),
+        [b'^', letter] => (1, *letter),
+        [b'_', b'_', letter] => (-2, *letter),
+        [b'_', letter] => (-1, *letter),
+        [b'=', letter] => (0, *letter),
+        _ => return None,
+    };
+
+    Some((
+        accidental,
+        letter_index(letter.to_ascii_uppercase() as char)?,
+    ))
+}
+
+struct AbcParser {
+    line: usize,
+    title: String,
+    bpm: Option<f32>,
+    /// Length of the `L:` unit note, in whole notes.
+    unit: Option<f32>,
+    /// Bar length from `M:`, in whole notes.
+    meter: f32,
+    key: Key,
+    /// Accidentals written earlier in the current bar, keyed by unaltered pitch.
+    bar_accidentals: HashMap<i32, i32>,
+    voice: Option<String>,
+    skip_voice: bool,
+    steps: Vec<Step>,
+    tempo_changes: Vec<TempoChange>,
+    tie: bool,
+    broken: Option<f32>,
+    tuplet: Option<(f32, usize)>,
+    repeat_start: usize,
+    first_ending: Option<usize>,
+}
+
+impl Default for AbcParser {
+    fn default() -> Self {
+        AbcParser {
+            line: 0,
+            title: String::new(),
+            bpm: None,
+            unit: None,
+            meter: 1.0,
+            key: Key::default(),
+            bar_accidentals: HashMap::new(),
+            voice: None,
+            skip_voice: false,
+            steps: vec![],
+            tempo_changes: vec![],
+            tie: false,
+            broken: None,
+            tuplet: None,
+            repeat_start: 0,
+            first_ending: None,
+        }
+    }
+}
+
+impl AbcParser {
+    fn syntax_error(&self, message: impl Into<String>) -> AbcError {
+        AbcError::Syntax {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn header_field(&mut self, field: char, value: &str) -> Result<(), AbcError> {
+        match field {
+            'T' if self.title.is_empty() => self.title = value.to_string(),
+            'Q' => self.bpm = self.bpm.or(self.tempo(value)),
+            'V' => self.voice(value),
+            _ => self.body_field(field, value)?,
+        }
+        Ok(())
+    }
+
+    fn body_field(&mut self, field: char, value: &str) -> Result<(), AbcError> {
+        match field {
+            'K' => {
+                self.key = Key::parse(value)
+                    .ok_or_else(|| self.syntax_error(format!("invalid key {value:?}")))?;
+            }
+            'L' => {
+                self.unit = Some(
+                    fraction(value)
+                        .ok_or_else(|| self.syntax_error(format!("invalid length {value:?}")))?,
+                );
+            }
+            'M' => {
+                self.meter = match value {
+                    "C" | "C|" | "none" | "" => 1.0,
+                    _ => fraction(value)
+                        .ok_or_else(|| self.syntax_error(format!("invalid meter {value:?}")))?,
+                };
+            }
+            'Q' => match self.tempo(value) {
+                // Tempo fields inside the tune become tempo changes
+                Some(bpm) if self.bpm.is_some() => self.tempo_changes.push(TempoChange {
+                    beat: self.steps.iter().map(|step| step.beats).sum(),
+                    bpm,
+                    ramp: 0.0,
+                }),
+                tempo => self.bpm = self.bpm.or(tempo),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Converts `Q:1/4=80` or a bare `Q:80` into quarter-note beats per minute.
+    fn tempo(&self, value: &str) -> Option<f32> {
+        let unquoted: String = value.split('"').step_by(2).collect();
+
+        match unquoted.split_once('=') {
+            Some((beat, bpm)) => {
+                let beat: f32 = beat.split_whitespace().filter_map(fraction).sum();
+                Some(bpm.trim().parse::<f32>().ok()? * beat * 4.0)
+            }
+            None => Some(unquoted.trim().parse::<f32>().ok()? * self.unit_length() * 4.0),
+        }
+    }
+
+    /// Only the first voice of a multi-voice tune is kept.
+    fn voice(&mut self, value: &str) {
+        let id = value.split_whitespace().next().unwrap_or("").to_string();
+        let first = self.voice.get_or_insert_with(|| id.clone());
+        self.skip_voice = *first != id;
+    }
+
+    fn unit_length(&self) -> f32 {
+        self.unit.unwrap_or(if self.meter < 0.75 {
+            1.0 / 16.0
+        } else {
+            1.0 / 8.0
+        })
+    }
+
+    fn body_line(&mut self, line: &str) -> Result<(), AbcError> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                'A'..='G' | 'a'..='g' | '^' | '_' | '=' => i = self.note(&chars, i)?,
+                'z' | 'x' => {
+                    i += 1;
+                    let length = self.length(&chars, &mut i)?;
+                    self.push(vec![], length);
+                }
+                'Z' | 'X' => {
+                    i += 1;
+                    let bars = self.number(&chars, &mut i).unwrap_or(1);
+                    self.push(vec![], bars as f32 * self.meter * 4.0);
+                }
+                '|' | ':' => i = self.bar_line(&chars, i),
+                '[' => match chars.get(i + 1) {
+                    Some(digit) if digit.is_ascii_digit() => {
+                        i += 1;
+                        self.ending(&chars, &mut i);
+                    }
+                    Some('|') => i = self.bar_line(&chars, i + 1),
+                    Some(&field) if chars.get(i + 2) == Some(&':') => {
+                        let end = chars[i..]
+                            .iter()
+                            .position(|&c| c == ']')
+                            .map(|end| i + end)
+                            .ok_or_else(|| self.syntax_error("unclosed inline field"))?;
+                        let value: String = chars[i + 3..end].iter().collect();
+                        self.body_field(field, value.trim())?;
+                        i = end + 1;
+                    }
+                    _ => i = self.chord(&chars, i)?,
+                },
+                '-' => {
+                    self.tie = true;
+                    i += 1;
+                }
+                '>' | '<' => {
+                    let mut dots = 0;
+                    while chars.get(i) == Some(&c) {
+                        dots += 1;
+                        i += 1;
+                    }
+                    let short = 0.5f32.powi(dots);
+                    let (previous, next) = if c == '>' {
+                        (2.0 - short, short)
+                    } else {
+                        (short, 2.0 - short)
+                    };
+                    if let Some(step) = self.steps.last_mut() {
+                        step.beats *= previous;
+                    }
+                    self.broken = Some(next);
+                }
+                '(' => {
+                    i += 1;
+                    if let Some(p) = self.number(&chars, &mut i) {
+                        let q = match p {
+                            2 | 4 | 8 => 3,
+                            _ => 2,
+                        };
+                        self.tuplet = Some((q as f32 / p as f32, p as usize));
+                    }
+                }
+                '{' => i = self.skip_past(&chars, i, '}')?,
+                '"' => i = self.skip_past(&chars, i, '"')?,
+                '!' => i = self.skip_past(&chars, i, '!')?,
+                '+' => i = self.skip_past(&chars, i, '+')?,
+                '&' => return Err(self.syntax_error("voice overlays are not supported")),
+                c if c.is_whitespace() => i += 1,
+                ')' | '.' | '~' | '\\' | '`' | '$' | 'y' | 'H' | 'L' | 'M' | 'O' | 'P' | 'S'
+                | 'T' | 'u' | 'v' | ']' => i += 1,
+                _ => return Err(self.syntax_error(format!("unexpected character {c:?}"))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn skip_past(&self, chars: &[char], start: usize, close: char) -> Result<usize, AbcError> {
+        chars[start + 1..]
+            .iter()
+            .position(|&c| c == close)
+            .map(|end| start + end + 2)
+            .ok_or_else(|| self.syntax_error(format!("missing closing {close:?}")))
+    }
+
+    fn number(&self, chars: &[char], i: &mut usize) -> Option<u32> {
+        let start = *i;
+        while chars.get(*i).is_some_and(char::is_ascii_digit) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect::<String>().parse().ok()
+    }
+
+    /// Reads a length such as `3`, `/`, `//`, `3/2` and returns it in beats.
+    fn length(&self, chars: &[char], i: &mut usize) -> Result<f32, AbcError> {
+        let mut multiplier = self.number(chars, i).unwrap_or(1) as f32;
+
+        while chars.get(*i) == Some(&'/') {
+            *i += 1;
+            match self.number(chars, i) {
+                Some(0) => return Err(self.syntax_error("note length divides by zero")),
+                Some(divisor) => multiplier /= divisor as f32,
+                None => multiplier /= 2.0,
+            }
+        }
+
+        Ok(multiplier * self.unit_length() * 4.0)
+    }
+
+    fn note(&mut self, chars: &[char], i: usize) -> Result<usize, AbcError> {
+        let (pitch, length, i) = self.pitch(chars, i)?;
+        self.push(vec![pitch], length);
+        Ok(i)
+    }
+
+    /// Reads a chord such as `[CEG]2`. It lasts as long as its first note,
+    /// scaled by any length written after the closing bracket.
+    fn chord(&mut self, chars: &[char], mut i: usize) -> Result<usize, AbcError> {
+        let mut pitches = vec![];
+        let mut length = None;
+
+        i += 1;
+        loop {
+            match chars.get(i) {
+                Some(']') => break,
+                Some('A'..='G' | 'a'..='g' | '^' | '_' | '=') => {
+                    let (pitch, note_length, next) = self.pitch(chars, i)?;
+                    pitches.push(pitch);
+                    length.get_or_insert(note_length);
+                    i = next;
+                }
+                Some('-') => {
+                    self.tie = true;
+                    i += 1;
+                }
+                Some(c) if c.is_whitespace() => i += 1,
+                Some(c) => return Err(self.syntax_error(format!("unexpected {c:?} in chord"))),
+                None => return Err(self.syntax_error("unclosed chord")),
+            }
+        }
+        i += 1;
+
+        let length = length.ok_or_else(|| self.syntax_error("empty chord"))?;
+        let multiplier = self.length(chars, &mut i)? / (self.unit_length() * 4.0);
+        self.push(pitches, length * multiplier);
+        Ok(i)
+    }
+
+    /// Reads one note and returns its MIDI pitch, its length in beats and the index after it.
+    fn pitch(&mut self, chars: &[char], mut i: usize) -> Result<(usize, f32, usize), AbcError> {
+        let mut accidental = None;
+        while let Some(&c) = chars.get(i) {
+            accidental = Some(match (c, accidental) {
+                ('^', None) => 1,
+                ('^', Some(1)) => 2,
+                ('_', None) => -1,
+                ('_', Some(-1)) => -2,
+                ('=', None) => 0,
+                _ => break,
+            });
+            i += 1;
+        }
+
+        let letter = *chars
+            .get(i)
+            .filter(|c| matches!(c, 'A'..='G' | 'a'..='g'))
+            .ok_or_else(|| self.syntax_error("accidental without a note"))?;
+        i += 1;
+
+        let letter_index = letter_index(letter.to_ascii_uppercase()).unwrap_or_default();
+        let mut pitch = 60 + LETTER_SEMITONES[letter_index];
+        if letter.is_ascii_lowercase() {
+            pitch += 12;
+        }
+        while let Some(&mark) = chars.get(i) {
+            match mark {
+                '\'' => pitch += 12,
+                ',' => pitch -= 12,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let accidental = match accidental {
+            Some(accidental) => {
+                self.bar_accidentals.insert(pitch, accidental);
+                accidental
+            }
+            None => self
+                .bar_accidentals
+                .get(&pitch)
+                .copied()
+                .unwrap_or(self.key.accidentals[letter_index]),
+        };
+
+        let length = self.length(chars, &mut i)?;
+        let midi = pitch + accidental + self.key.transpose;
+        if !(0..=127).contains(&midi) {
+            return Err(AbcError::PitchOutOfRange {
+                line: self.line,
+                pitch: midi,
+            });
+        }
+
+        Ok((midi as usize, length, i))
+    }
+
+    fn push(&mut self, pitches: Vec<usize>, length: f32) {
+        let mut length = length * self.broken.take().unwrap_or(1.0);
+        if let Some((factor, remaining)) = self.tuplet.take() {
+            length *= factor;
+            if remaining > 1 {
+                self.tuplet = Some((factor, remaining - 1));
+            }
+        }
+
+        let step = Step::new(&pitches, length);
+        let tied = std::mem::take(&mut self.tie);
+        match self.steps.last_mut() {
+            Some(last) if tied && !step.is_rest() && last.notes == step.notes => {
+                last.beats += length;
+            }
+            _ => self.steps.push(step),
+        }
+    }
+
+    fn bar_line(&mut self, chars: &[char], start: usize) -> usize {
+        let mut i = start;
+        while chars.get(i).is_some_and(|c| matches!(c, '|' | ':' | ']')) {
+            i += 1;
+        }
+        let bar: String = chars[start..i].iter().collect();
+
+        self.bar_accidentals.clear();
+
+        if bar.starts_with(':') {
+            let end = self.first_ending.take().unwrap_or(self.steps.len());
+            let repeat = self.steps[self.repeat_start..end].to_vec();
+            self.steps.extend(repeat);
+            self.repeat_start = self.steps.len();
+        }
+        if bar.len() > 1 && bar.ends_with(':') {
+            // A first ending left open before this section has nothing to skip
+            self.repeat_start = self.steps.len();
+            self.first_ending = None;
+        }
+
+        if chars.get(i) == Some(&'[') && chars.get(i + 1).is_some_and(char::is_ascii_digit) {
+            i += 1;
+        }
+        if chars.get(i).is_some_and(char::is_ascii_digit) {
+            self.ending(chars, &mut i);
+        }
+
+        i
+    }
+
+    /// Handles `[1`/`|1` style ending markers. Only the first ending needs
+    /// tracking, since it is skipped when the section repeats.
+    fn ending(&mut self, chars: &[char], i: &mut usize) {
+        let number = self.number(chars, i);
+        while chars
+            .get(*i)
+            .is_some_and(|c| matches!(c, ',' | '-' | '0'..='9'))
+        {
+            *i += 1;
+        }
+        if number == Some(1) {
+            self.first_ending = Some(self.steps.len());
+        }
+    }
+
+    fn finish(self) -> Song {
+        let title = if self.title.is_empty() {
+            "Untitled".to_string()
+        } else {
+            self.title
+        };
+        Song::new(title, self.bpm.unwrap_or(BPM), self.steps).with_tempo_changes(self.tempo_changes)
+    }
+}
+
+#[derive(Default)]
+pub struct AbcLoader;
+
+impl AssetLoader for AbcLoader {
+    type Asset = Song;
+    type Settings = ();
+    type Error = AbcError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            song_from_abc(std::str::from_utf8(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["abc"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a tune and returns its steps as (MIDI pitches, beats).
+    fn steps(source: &str) -> Vec<(Vec<usize>, f32)> {
+        song_from_abc(source)
+            .unwrap()
+            .notes
+            .into_iter()
+            .map(|step| (step.notes.iter().map(|note| note.0).collect(), step.beats))
+            .collect()
+    }
+
+    fn pitches(source: &str) -> Vec<usize> {
+        steps(source)
+            .into_iter()
+            .flat_map(|(pitches, _)| pitches)
+            .collect()
+    }
+
+    #[test]
+    fn parses_the_last_hymn() {
+        // (index into the D3-based scale, length in eighth notes)
+        #[rustfmt::skip]
+        let melody = [
+            (2, 1), (6, 1), (9, 3), (11, 1), (9, 1), (6, 1), (2, 4), (2, 2), (4, 3), (4, 1),
+            (2, 1), (4, 1), (6, 4), (2, 1), (6, 1), (9, 3), (11, 1), (9, 1), (6, 1), (2, 4),
+            (2, 1), (4, 1), (6, 3), (7, 1), (6, 1), (4, 1), (2, 6), (9, 3), (11, 1), (9, 1),
+            (6, 1), (14, 6), (9, 3), (11, 1), (9, 1), (6, 1), (4, 6), (9, 3), (11, 1), (9, 1),
+            (6, 1), (14, 1), (13, 1), (11, 2), (14, 2), (6, 3), (7, 1), (6, 1), (4, 1), (2, 4),
+        ];
+        let expected: Vec<(Vec<usize>, f32)> = melody
+            .iter()
+            .map(|&(index, eighths)| (vec![48 + index], eighths as f32 / 2.0))
+            .collect();
+
+        let song = song_from_abc(include_str!("../../assets/songs/the_last_hymn.abc")).unwrap();
+        assert_eq!(song.title, "The Last Hymn");
+        assert_eq!(song.bpm, 80.0);
+        assert_eq!(
+            steps(include_str!("../../assets/songs/the_last_hymn.abc")),
+            expected
+        );
+    }
+
+    #[test]
+    fn applies_key_signatures_and_bar_accidentals() {
+        assert_eq!(pitches("X:1\nK:Bb\nBEF\n"), [70, 63, 65]);
+        assert_eq!(pitches("X:1\nK:Ador\nFc\n"), [66, 72]);
+        assert_eq!(pitches("X:1\nK:D octave=-1\nDf\n"), [50, 66]);
+        assert_eq!(pitches("X:1\nK:C ^f\nF\n"), [66]);
+        // Accidentals last until the end of the bar
+        assert_eq!(pitches("X:1\nK:G\nF=FF|F\n"), [66, 65, 65, 66]);
+    }
+
+    #[test]
+    fn parses_rests_and_ties() {
+        assert_eq!(
+            steps("X:1\nL:1/8\nK:C\nCz2D\n"),
+            [(vec![60], 0.5), (vec![], 1.0), (vec![62], 0.5)]
+        );
+        assert_eq!(steps("X:1\nL:1/8\nK:C\nC2-C2\n"), [(vec![60], 2.0)]);
+        assert_eq!(
+            steps("X:1\nL:1/8\nK:C\nC-D\n"),
+            [(vec![60], 0.5), (vec![62], 0.5)]
+        );
+    }
+
+    #[test]
+    fn parses_broken_rhythms_and_triplets() {
+        let beats = |source: &str| {
+            steps(source)
+                .into_iter()
+                .map(|(_, beats)| beats)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(beats("X:1\nL:1/8\nK:C\nC>D\n"), [0.75, 0.25]);
+        assert_eq!(beats("X:1\nL:1/8\nK:C\nC<D\n"), [0.25, 0.75]);
+        assert_eq!(beats("X:1\nL:1/8\nK:C\nC>>D\n"), [0.875, 0.125]);
+
+        let triplet = beats("X:1\nL:1/8\nK:C\n(3CDE F\n");
+        assert_eq!(triplet.len(), 4);
+        for beats in &triplet[..3] {
+            assert!((beats - 1.0 / 3.0).abs() < 1e-6);
+        }
+        assert_eq!(triplet[3], 0.5);
+    }
+
+    #[test]
+    fn expands_repeats_and_endings() {
+        assert_eq!(
+            pitches("X:1\nK:C\n|: C D |1 E :|2 F |]\n"),
+            [60, 62, 64, 60, 62, 65]
+        );
+        assert_eq!(
+            pitches("X:1\nK:C\nC |: D E :| F\n"),
+            [60, 62, 64, 62, 64, 65]
+        );
+        // A first ending that is never closed does not leak into the next repeat
+        assert_eq!(
+            pitches("X:1\nK:C\nA B |1 C D | E F |: G A :|\n"),
+            [69, 71, 60, 62, 64, 65, 67, 69, 67, 69]
+        );
+    }
+}
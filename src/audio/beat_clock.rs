@@ -0,0 +1,75 @@
+// beat_clock.rs
+use super::CurrentBPM;
+use bevy::prelude::*;
+
+pub const BEATS_PER_BAR: u32 = 4;
+
+/// The shared musical clock. Advances at `CurrentBPM` and is the single
+/// source of beats and bars for every rhythmic system.
+#[derive(Resource)]
+pub struct BeatClock {
+    pub beats_per_bar: u32,
+    /// Beats elapsed since the clock was reset, including the fraction of the current beat.
+    pub position: f64,
+    started: bool,
+}
+
+impl Default for BeatClock {
+    fn default() -> Self {
+        BeatClock {
+            beats_per_bar: BEATS_PER_BAR,
+            position: 0.0,
+            started: false,
+        }
+    }
+}
+
+impl BeatClock {
+    pub fn beat(&self) -> u64 {
+        self.position as u64
+    }
+
+    pub fn bar(&self) -> u64 {
+        self.beat() / self.beats_per_bar as u64
+    }
+
+    /// How far through the current beat the clock is, from 0 to 1.
+    pub fn phase(&self) -> f32 {
+        self.position.fract() as f32
+    }
+
+    /// Restarts the clock so the next frame lands on a downbeat.
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+        self.started = false;
+    }
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BeatEvent {
+    /// Beat within its bar, starting at 0 on the downbeat.
+    pub beat_in_bar: u32,
+}
+
+pub fn tick_beat_clock(
+    time: Res<Time>,
+    current_bpm: Res<CurrentBPM>,
+    mut clock: ResMut<BeatClock>,
+    mut beat_events: EventWriter<BeatEvent>,
+) {
+    // The very first beat is sent on the first tick rather than skipped
+    let first_beat = if clock.started {
+        clock.beat() + 1
+    } else {
+        clock.beat()
+    };
+    clock.started = true;
+    clock.position += time.delta_seconds_f64() * current_bpm.bpm as f64 / 60.0;
+
+    let beats_per_bar = clock.beats_per_bar as u64;
+    for beat in first_beat..=clock.beat() {
+        beat_events.send(BeatEvent {
+            beat_in_bar: (beat % beats_per_bar) as u32,
+        });
+    }
+}
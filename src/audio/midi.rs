@@ -0,0 +1,441 @@
+// midi.rs
+use super::{Note, Song, SongTimingError, Step, TempoChange};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::utils::BoxedFuture;
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Tempo assumed by the MIDI spec when a file has no tempo event.
+const DEFAULT_MIDI_BPM: f32 = 120.0;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum MidiImportError {
+    #[error("could not read MIDI file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse MIDI file: {0}")]
+    Parse(#[from] midly::Error),
+    #[error("type 2 (sequential) MIDI files are not supported")]
+    SequentialFormat,
+    #[error("SMPTE timecode MIDI files are not supported")]
+    TimecodeTiming,
+    #[error("MIDI file has no track with notes")]
+    NoNotes,
+    #[error(
+        "MIDI pitch {pitch} at tick {tick} is out of reach of the note samples, even transposed"
+    )]
+    PitchOutOfRange { pitch: u8, tick: u32 },
+    #[error("invalid MIDI timing: {0}")]
+    Timing(#[from] SongTimingError),
+}
+
+/// A note with its start and end in absolute ticks.
+struct MidiNote {
+    pitch: u8,
+    start: u32,
+    end: u32,
+}
+
+/// Converts a Type 0/1 MIDI file into a `Song`. The first track with notes
+/// becomes the melody and the second, if any, the harmony. Notes are quantized
+/// to the eighth-note grid, notes starting together become chords, and notes
+/// still sounding when the next chord starts are cut short. Tempo events after
+/// the start, on any track, become tempo changes. Songs are moved into the
+/// range of the note samples when played, so the notes may span no more than it.
+pub fn song_from_midi(title: &str, bytes: &[u8]) -> Result<Song, MidiImportError> {
+    let smf = Smf::parse(bytes)?;
+
+    if smf.header.format == Format::Sequential {
+        return Err(MidiImportError::SequentialFormat);
+    }
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks) => ticks.as_int() as u32,
+        Timing::Timecode(..) => return Err(MidiImportError::TimecodeTiming),
+    };
+
+    let mut tempos = vec![];
+    let mut track_name = None;
+    let mut voices = vec![];
+
+    for track in &smf.tracks {
+        let mut tick = 0;
+        let mut sounding: HashMap<u8, u32> = HashMap::new();
+        let mut track_notes = vec![];
+
+        for event in track {
+            tick += event.delta.as_int();
+
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(micros_per_beat)) => {
+                    tempos.push((tick, 60_000_000.0 / micros_per_beat.as_int() as f32));
+                }
+                TrackEventKind::Meta(MetaMessage::TrackName(name)) if track_name.is_none() => {
+                    track_name = Some(String::from_utf8_lossy(name).into_owned());
+                }
+                TrackEventKind::Midi { message, .. } => {
+                    let (key, note_on) = match message {
+                        MidiMessage::NoteOn { key, vel } => (key.as_int(), vel > 0),
+                        MidiMessage::NoteOff { key, .. } => (key.as_int(), false),
+                        _ => continue,
+                    };
+
+                    // Retriggering a key ends the note it was already playing
+                    if let Some(start) = sounding.remove(&key) {
+                        track_notes.push(MidiNote {
+                            pitch: key,
+                            start,
+                            end: tick,
+                        });
+                    }
+                    if note_on {
+                        sounding.insert(key, tick);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (pitch, start) in sounding {
+            track_notes.push(MidiNote {
+                pitch,
+                start,
+                end: tick,
+            });
+        }
+
+        // Later tracks are still read for their tempo events
+        if !track_notes.is_empty() && voices.len() < 2 {
+            voices.push(track_notes);
+        }
+    }
+
+    let (low, high) = Note::sample_range();
+    let notes = voices.iter().flatten();
+    let lowest = notes.clone().map(|note| note.pitch).min();
+    let highest = notes.max_by_key(|note| (note.pitch, Reverse(note.start)));
+    if let (Some(lowest), Some(highest)) = (lowest, highest) {
+        if (highest.pitch - lowest) as usize > high.0 - low.0 {
+            return Err(MidiImportError::PitchOutOfRange {
+                pitch: highest.pitch,
+                tick: highest.start,
+            });
+        }
+    }
+
+    let mut voices = voices.into_iter();
+    let Some(melody) = voices.next() else {
+        return Err(MidiImportError::NoNotes);
+    };
+
+    let title = track_name.unwrap_or_else(|| title.to_string());
+    tempos.sort_by_key(|&(tick, _)| tick);
+    let bpm = match tempos.first() {
+        Some(&(0, bpm)) => bpm,
+        _ => DEFAULT_MIDI_BPM,
+    };
+    let tempo_changes = tempos
+        .into_iter()
+        .filter(|&(tick, _)| tick > 0)
+        .map(|(tick, bpm)| TempoChange {
+            beat: tick as f32 / ticks_per_beat as f32,
+            bpm,
+            ramp: 0.0,
+        })
+        .collect();
+    let eighth_ticks = ticks_per_beat as f32 / 2.0;
+    let quantize = |tick: u32| (tick as f32 / eighth_ticks).round() as u32;
+
+    let song = Song::new(title, bpm, steps(melody, quantize))
+        .with_harmony(
+            voices
+                .next()
+                .map_or(vec![], |harmony| steps(harmony, quantize)),
+        )
+        .with_tempo_changes(tempo_changes);
+    song.check_timing()?;
+    Ok(song)
+}
+
+/// Groups a track's notes into steps on the grid given by `quantize`, which maps
+/// ticks to eighth notes.
+fn steps(mut notes: Vec<MidiNote>, quantize: impl Fn(u32) -> u32) -> Vec<Step> {
+    notes.sort_by_key(|note| (note.start, note.pitch));
+
+    // (start, end, pitches) in eighth notes, one entry per chord
+    let mut chords: Vec<(u32, u32, Vec<usize>)> = vec![];
+    for note in notes {
+        let start = quantize(note.start);
+        let end = quantize(note.end).max(start + 1);
+
+        match chords.last_mut() {
+            Some((chord_start, chord_end, pitches)) if *chord_start == start => {
+                *chord_end = (*chord_end).max(end);
+                if !pitches.contains(&(note.pitch as usize)) {
+                    pitches.push(note.pitch as usize);
+                }
+            }
+            _ => chords.push((start, end, vec![note.pitch as usize])),
+        }
+    }
+
+    let mut steps = vec![];
+    let mut cursor = 0;
+
+    for (i, (start, end, pitches)) in chords.iter().enumerate() {
+        if *start > cursor {
+            steps.push(Step::rest((start - cursor) as f32 / 2.0));
+        }
+        let end = match chords.get(i + 1) {
+            Some(&(next_start, ..)) => (*end).min(next_start),
+            None => *end,
+        };
+        steps.push(Step::new(pitches, (end - start) as f32 / 2.0));
+        cursor = end;
+    }
+
+    steps
+}
+
+#[derive(Default)]
+pub struct MidiLoader;
+
+impl AssetLoader for MidiLoader {
+    type Asset = Song;
+    type Settings = ();
+    type Error = MidiImportError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let title = load_context
+                .path()
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            song_from_midi(&title, &bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["mid", "midi"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKS_PER_BEAT: u16 = 96;
+    const EIGHTH: u32 = TICKS_PER_BEAT as u32 / 2;
+
+    /// Builds a Standard MIDI File out of tracks made by `track`.
+    fn smf(format: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend(6u32.to_be_bytes());
+        bytes.extend(format.to_be_bytes());
+        bytes.extend((tracks.len() as u16).to_be_bytes());
+        bytes.extend(TICKS_PER_BEAT.to_be_bytes());
+        for track in tracks {
+            bytes.extend(b"MTrk");
+            bytes.extend((track.len() as u32).to_be_bytes());
+            bytes.extend(track);
+        }
+        bytes
+    }
+
+    /// Builds a track's events out of (delta ticks, event bytes), closing it
+    /// with an end of track.
+    fn track(events: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (delta, event) in events.iter().chain([&(0, vec![0xFF, 0x2F, 0x00])]) {
+            // Variable-length quantity, seven bits a byte, most significant first
+            let mut groups = vec![(delta & 0x7F) as u8];
+            let mut rest = delta >> 7;
+            while rest > 0 {
+                groups.push((rest & 0x7F) as u8 | 0x80);
+                rest >>= 7;
+            }
+            bytes.extend(groups.iter().rev());
+            bytes.extend(event);
+        }
+        bytes
+    }
+
+    fn on(pitch: u8) -> Vec<u8> {
+        vec![0x90, pitch, 100]
+    }
+
+    fn off(pitch: u8) -> Vec<u8> {
+        vec![0x80, pitch, 0]
+    }
+
+    fn tempo(bpm: u32) -> Vec<u8> {
+        let [_, a, b, c] = (60_000_000 / bpm).to_be_bytes();
+        vec![0xFF, 0x51, 0x03, a, b, c]
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0x03, name.len() as u8];
+        bytes.extend(name.as_bytes());
+        bytes
+    }
+
+    /// One note after another, each `eighths` long, with no rests.
+    fn melody(notes: &[(u8, u32)]) -> Vec<(u32, Vec<u8>)> {
+        notes
+            .iter()
+            .flat_map(|&(pitch, eighths)| [(0, on(pitch)), (eighths * EIGHTH, off(pitch))])
+            .collect()
+    }
+
+    #[test]
+    fn imports_a_type_0_file() {
+        let mut events = vec![(0, name("Hymn")), (0, tempo(100))];
+        events.extend(melody(&[(60, 2), (62, 1), (64, 3)]));
+        let song = song_from_midi("file", &smf(0, &[track(&events)])).unwrap();
+
+        assert_eq!(song.title, "Hymn");
+        assert_eq!(song.bpm, 100.0);
+        assert_eq!(
+            song.notes,
+            [
+                Step::new(&[60], 1.0),
+                Step::new(&[62], 0.5),
+                Step::new(&[64], 1.5)
+            ]
+        );
+        assert!(song.harmony.is_empty());
+        assert!(song.tempo_changes.is_empty());
+    }
+
+    #[test]
+    fn imports_a_type_1_file() {
+        let tracks = [
+            track(&[(0, tempo(80))]),
+            track(&melody(&[(60, 2), (64, 2)])),
+            track(&melody(&[(55, 4)])),
+            // Only two voices are played
+            track(&melody(&[(67, 4)])),
+        ];
+        let song = song_from_midi("file", &smf(1, &tracks)).unwrap();
+
+        assert_eq!(song.title, "file");
+        assert_eq!(song.bpm, 80.0);
+        assert_eq!(song.notes, [Step::new(&[60], 1.0), Step::new(&[64], 1.0)]);
+        assert_eq!(song.harmony, [Step::new(&[55], 2.0)]);
+    }
+
+    #[test]
+    fn notes_starting_together_become_a_chord() {
+        let events = [
+            (0, on(60)),
+            (0, on(64)),
+            (0, on(67)),
+            (2 * EIGHTH, off(60)),
+            (0, off(64)),
+            (0, off(67)),
+            (0, on(62)),
+            // Still sounding when the next chord starts, so cut short
+            (0, on(65)),
+            (EIGHTH, on(64)),
+            (EIGHTH, off(62)),
+            (0, off(65)),
+            (0, off(64)),
+        ];
+        let song = song_from_midi("file", &smf(0, &[track(&events)])).unwrap();
+
+        assert_eq!(
+            song.notes,
+            [
+                Step::new(&[60, 64, 67], 1.0),
+                Step::new(&[62, 65], 0.5),
+                Step::new(&[64], 0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn quantizes_to_eighth_notes() {
+        // Played a little early, a little late and a little short
+        let events = [
+            (EIGHTH + 5, on(60)),
+            (2 * EIGHTH - 10, off(60)),
+            (3, on(62)),
+            (EIGHTH - 20, off(62)),
+        ];
+        let song = song_from_midi("file", &smf(0, &[track(&events)])).unwrap();
+
+        assert_eq!(
+            song.notes,
+            [
+                Step::rest(0.5),
+                Step::new(&[60], 1.0),
+                Step::new(&[62], 0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_tempo_changes_from_every_track() {
+        let tracks = [
+            track(&[(0, tempo(120)), (4 * EIGHTH, tempo(60))]),
+            track(&melody(&[(60, 8)])),
+            track(&melody(&[(55, 8)])),
+            track(&[(6 * EIGHTH, tempo(80))]),
+        ];
+        let song = song_from_midi("file", &smf(1, &tracks)).unwrap();
+
+        assert_eq!(song.bpm, 120.0);
+        assert_eq!(
+            song.tempo_changes,
+            [
+                TempoChange {
+                    beat: 2.0,
+                    bpm: 60.0,
+                    ramp: 0.0
+                },
+                TempoChange {
+                    beat: 3.0,
+                    bpm: 80.0,
+                    ramp: 0.0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_type_2_files() {
+        let tracks = [track(&melody(&[(60, 2)])), track(&melody(&[(62, 2)]))];
+        assert!(matches!(
+            song_from_midi("file", &smf(2, &tracks)),
+            Err(MidiImportError::SequentialFormat)
+        ));
+    }
+
+    #[test]
+    fn rejects_notes_too_far_apart_for_the_samples() {
+        let (low, high) = Note::sample_range();
+        let span = (high.0 - low.0) as u8;
+
+        // Far from the samples, but close enough together to be moved there
+        let events = melody(&[(90, 2), (90 + span, 2)]);
+        assert!(song_from_midi("file", &smf(0, &[track(&events)])).is_ok());
+
+        let events = melody(&[(60, 2), (61 + span, 2), (61 + span, 2)]);
+        assert!(matches!(
+            song_from_midi("file", &smf(0, &[track(&events)])),
+            Err(MidiImportError::PitchOutOfRange { pitch, tick })
+                if pitch == 61 + span && tick == 2 * EIGHTH
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+// mixer.rs
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy_kira_audio::{AudioChannel, AudioControl, AudioTween};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+
+/// Where the mixer settings are kept between runs.
+pub const SETTINGS_PATH: &str = "settings.ron";
+/// Gain the note samples are played at, before the notes channel volume.
+pub const NOTE_VOLUME: f64 = 5.0;
+/// How long channels take to duck under a note and to come back.
+pub const DUCKING_SECONDS: f32 = 0.1;
+
+#[derive(Resource)]
+pub struct MusicChannel;
+
+#[derive(Resource)]
+pub struct NotesChannel;
+
+#[derive(Resource)]
+pub struct SfxChannel;
+
+#[derive(Resource)]
+pub struct AmbienceChannel;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Channel {
+    #[default]
+    Music,
+    Notes,
+    Sfx,
+    Ambience,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelSettings {
+    pub volume: f32,
+    pub muted: bool,
+    /// How loud the channel stays while a note plays over it, from 0 to 1.
+    pub ducking: f32,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            volume: 1.0,
+            muted: false,
+            ducking: 1.0,
+        }
+    }
+}
+
+#[derive(Resource, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MixerSettings {
+    pub music: ChannelSettings,
+    pub notes: ChannelSettings,
+    pub sfx: ChannelSettings,
+    pub ambience: ChannelSettings,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        let ducked = ChannelSettings {
+            ducking: 0.5,
+            ..default()
+        };
+        MixerSettings {
+            music: ducked,
+            notes: ChannelSettings::default(),
+            sfx: ChannelSettings::default(),
+            ambience: ducked,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("could not access settings file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse settings file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write settings file: {0}")]
+    Write(#[from] ron::Error),
+}
+
+impl MixerSettings {
+    pub fn channel(&self, channel: Channel) -> &ChannelSettings {
+        match channel {
+            Channel::Music => &self.music,
+            Channel::Notes => &self.notes,
+            Channel::Sfx => &self.sfx,
+            Channel::Ambience => &self.ambience,
+        }
+    }
+
+    pub fn channel_mut(&mut self, channel: Channel) -> &mut ChannelSettings {
+        match channel {
+            Channel::Music => &mut self.music,
+            Channel::Notes => &mut self.notes,
+            Channel::Sfx => &mut self.sfx,
+            Channel::Ambience => &mut self.ambience,
+        }
+    }
+
+    /// Volume multiplier for sounds on `channel`, 0 when it is muted.
+    pub fn volume(&self, channel: Channel) -> f64 {
+        let settings = self.channel(channel);
+        if settings.muted {
+            0.0
+        } else {
+            settings.volume as f64
+        }
+    }
+
+    /// `volume` for `channel`, lowered by its ducking while notes are playing.
+    pub fn ducked_volume(&self, channel: Channel, ducking: bool) -> f64 {
+        let duck = if ducking {
+            self.channel(channel).ducking as f64
+        } else {
+            1.0
+        };
+        self.volume(channel) * duck
+    }
+
+    pub fn read(path: &Path) -> Result<MixerSettings, SettingsError> {
+        let bytes = std::fs::read(path)?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), SettingsError> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Reads `SETTINGS_PATH`, falling back to the defaults if it is missing or broken.
+    pub fn load() -> MixerSettings {
+        let path = Path::new(SETTINGS_PATH);
+        if !path.exists() {
+            return MixerSettings::default();
+        }
+
+        MixerSettings::read(path).unwrap_or_else(|error| {
+            warn!("{error}, using default mixer settings");
+            MixerSettings::default()
+        })
+    }
+}
+
+/// Ducks music and ambience while notes are playing.
+#[derive(Resource, Default)]
+pub struct Ducking {
+    /// `Time::elapsed_seconds` at which the ducking ends.
+    pub until: f32,
+}
+
+impl Ducking {
+    /// Keeps the music ducked for at least `seconds` from `now`.
+    pub fn duck(&mut self, now: f32, seconds: f32) {
+        self.until = self.until.max(now + seconds);
+    }
+
+    pub fn is_ducking(&self, now: f32) -> bool {
+        now < self.until
+    }
+}
+
+/// Sets the volume of the notes and sfx channels, reaching the sounds already
+/// playing on them, as the mixer changes and as notes duck them. Music and
+/// ambience stems are mixed one by one in `mix_music_layers`.
+pub fn apply_channel_volumes(
+    mixer: Res<MixerSettings>,
+    ducking: Res<Ducking>,
+    mut was_ducking: Local<bool>,
+    time: Res<Time>,
+    notes_channel: Res<AudioChannel<NotesChannel>>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+) {
+    let is_ducking = ducking.is_ducking(time.elapsed_seconds());
+    let duck_changed = is_ducking != *was_ducking;
+    *was_ducking = is_ducking;
+
+    if !mixer.is_changed() && !duck_changed {
+        return;
+    }
+
+    let tween = AudioTween::linear(Duration::from_secs_f32(DUCKING_SECONDS));
+    notes_channel
+        .set_volume(NOTE_VOLUME * mixer.ducked_volume(Channel::Notes, is_ducking))
+        .fade_in(tween.clone());
+    sfx_channel
+        .set_volume(mixer.ducked_volume(Channel::Sfx, is_ducking))
+        .fade_in(tween);
+}
+
+/// 1-4 pick a channel, - and = turn it down or up, M mutes it.
+pub fn mixer_controls(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut selected: Local<Channel>,
+    mut settings: ResMut<MixerSettings>,
+) {
+    let channels = [
+        (KeyCode::Digit1, Channel::Music),
+        (KeyCode::Digit2, Channel::Notes),
+        (KeyCode::Digit3, Channel::Sfx),
+        (KeyCode::Digit4, Channel::Ambience),
+    ];
+    for (key, channel) in channels {
+        if keyboard.just_pressed(key) {
+            *selected = channel;
+            info!("Mixer channel: {:?}", channel);
+        }
+    }
+
+    let step = if keyboard.just_pressed(KeyCode::Equal) {
+        0.1
+    } else if keyboard.just_pressed(KeyCode::Minus) {
+        -0.1
+    } else {
+        0.0
+    };
+    let mute = keyboard.just_pressed(KeyCode::KeyM);
+    if step == 0.0 && !mute {
+        return;
+    }
+
+    let channel = settings.channel_mut(*selected);
+    channel.volume = ((channel.volume + step) * 10.0).round().clamp(0.0, 10.0) / 10.0;
+    channel.muted ^= mute;
+    info!("{:?}: {:?}", *selected, channel);
+}
+
+pub fn save_mixer_settings(settings: Res<MixerSettings>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+
+    if let Err(error) = settings.write(Path::new(SETTINGS_PATH)) {
+        warn!("{error}");
+    }
+}
@@ -0,0 +1,123 @@
+// audio.rs
+pub mod abc;
+pub mod beat_clock;
+pub mod midi;
+pub mod mixer;
+pub mod pitch;
+pub mod render;
+pub mod song;
+pub mod synth;
+
+pub use abc::*;
+pub use beat_clock::*;
+pub use midi::*;
+pub use mixer::*;
+pub use pitch::*;
+pub use render::*;
+pub use song::*;
+pub use synth::*;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_kira_audio::AudioSource;
+
+pub const NOTES: &[&str] = &[
+    "C3.wav",
+    "Csharp3.wav",
+    "D3.wav",
+    "Dsharp3.wav",
+    "E3.wav",
+    "F3.wav",
+    "Fsharp3.wav",
+    "G3.wav",
+    "Gsharp3.wav",
+    "A3.wav",
+    "Asharp3.wav",
+    "B3.wav",
+    "C4.wav",
+    "Csharp4.wav",
+    "D4.wav",
+];
+pub const FIRST_NOTE_MIDI: u8 = 48; // MIDI pitch of NOTES[0] (C3)
+pub const BPM: f32 = 80.0; // Beats per minute
+
+#[derive(Resource)]
+pub struct CurrentBPM {
+    pub bpm: f32,
+}
+
+impl CurrentBPM {
+    pub fn new(bpm: f32) -> Self {
+        CurrentBPM { bpm }
+    }
+
+    /// Converts a length in beats to seconds at this tempo.
+    pub fn seconds(&self, beats: f32) -> f32 {
+        beats * 60.0 / self.bpm
+    }
+}
+
+impl Default for CurrentBPM {
+    fn default() -> Self {
+        CurrentBPM::new(BPM)
+    }
+}
+
+#[derive(Resource)]
+pub struct NoteAudioHandles {
+    pub samples: Vec<Handle<AudioSource>>,
+    /// Synthesized notes of the current `Instrument`, rendered the first time they are played.
+    pub synth: HashMap<Note, Handle<AudioSource>>,
+}
+
+impl NoteAudioHandles {
+    /// Returns the sound for `note` on `instrument`, or `None` if the instrument can't play it.
+    pub fn handle(
+        &mut self,
+        note: Note,
+        instrument: &Instrument,
+        audio_sources: &mut Assets<AudioSource>,
+    ) -> Option<Handle<AudioSource>> {
+        match instrument {
+            Instrument::Samples => note.sample_index().map(|index| self.samples[index].clone()),
+            Instrument::Synth(voice) => Some(
+                self.synth
+                    .entry(note)
+                    .or_insert_with(|| audio_sources.add(voice.sound(note)))
+                    .clone(),
+            ),
+        }
+    }
+}
+
+/// Looks up the sound of a note on the selected `Instrument`.
+#[derive(SystemParam)]
+pub struct NoteSounds<'w> {
+    handles: ResMut<'w, NoteAudioHandles>,
+    instrument: Res<'w, Instrument>,
+    audio_sources: ResMut<'w, Assets<AudioSource>>,
+}
+
+impl NoteSounds<'_> {
+    /// Returns the sound for `note`, or `None` if the instrument can't play it.
+    pub fn handle(&mut self, note: Note) -> Option<Handle<AudioSource>> {
+        self.handles
+            .handle(note, &self.instrument, &mut self.audio_sources)
+    }
+}
+
+pub fn setup_audio(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let samples = NOTES.iter().map(|file| asset_server.load(*file)).collect();
+    commands.insert_resource(NoteAudioHandles {
+        samples,
+        synth: HashMap::new(),
+    });
+}
+
+/// Drops the rendered notes of the previous synth voice when the instrument changes.
+pub fn clear_synth_notes(instrument: Res<Instrument>, mut note_handles: ResMut<NoteAudioHandles>) {
+    if instrument.is_changed() {
+        note_handles.synth.clear();
+    }
+}
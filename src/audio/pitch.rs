@@ -0,0 +1,217 @@
+// pitch.rs
+use super::{FIRST_NOTE_MIDI, NOTES};
+use bevy::prelude::*;
+use std::fmt;
+
+/// Semitone offset of each note letter from C, indexed C, D, E, F, G, A, B.
+pub const LETTER_SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+pub const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+/// Letters in the order sharps are added to a key signature, as indices into `LETTERS`.
+const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+/// Letters in the order flats are added to a key signature.
+const FLAT_ORDER: [usize; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+pub fn letter_index(letter: char) -> Option<usize> {
+    LETTERS.iter().position(|&l| l == letter)
+}
+
+/// A MIDI pitch number. 60 is middle C (C4) and 69 is A4 at 440 Hz.
+#[derive(Clone, Copy, Component, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Note(pub usize);
+
+impl Note {
+    /// The note whose sample is `NOTES[index]`.
+    pub fn from_sample_index(index: usize) -> Note {
+        Note(FIRST_NOTE_MIDI as usize + index)
+    }
+
+    /// Index into `NOTES`, if there is a sample for this pitch.
+    pub fn sample_index(self) -> Option<usize> {
+        self.0
+            .checked_sub(FIRST_NOTE_MIDI as usize)
+            .filter(|&index| index < NOTES.len())
+    }
+
+    /// The lowest and highest notes there are samples for.
+    pub fn sample_range() -> (Note, Note) {
+        (
+            Note::from_sample_index(0),
+            Note::from_sample_index(NOTES.len() - 1),
+        )
+    }
+
+    pub fn frequency(self) -> f32 {
+        440.0 * 2f32.powf((self.0 as f32 - 69.0) / 12.0)
+    }
+
+    /// Semitones above C, from 0 to 11.
+    pub fn pitch_class(self) -> i32 {
+        self.0 as i32 % 12
+    }
+
+    /// Moves the note by `semitones`, or `None` if that leaves the MIDI range.
+    pub fn transpose(self, semitones: i32) -> Option<Note> {
+        let midi = self.0 as i32 + semitones;
+        (0..=127).contains(&midi).then_some(Note(midi as usize))
+    }
+
+    /// The note's name spelled for `key`, such as `F#3` in D major or `Gb3` in Db major.
+    pub fn name_in(self, key: KeySignature) -> String {
+        let (letter, accidental) = key.spell(self.pitch_class());
+        // B# and Cb belong to the octave of the C they sit next to
+        let octave = (self.0 as i32 - LETTER_SEMITONES[letter] - accidental) / 12 - 1;
+        let accidental = match accidental {
+            2 => "##",
+            1 => "#",
+            -1 => "b",
+            -2 => "bb",
+            _ => "",
+        };
+
+        format!("{}{}{}", LETTERS[letter], accidental, octave)
+    }
+
+    /// The note's name using sharps, such as `C#4`.
+    pub fn name(self) -> String {
+        self.name_in(KeySignature::default())
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+/// A key signature as a position on the circle of fifths: positive counts
+/// sharps, negative counts flats. Major and minor keys sharing a signature
+/// are the same here.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct KeySignature {
+    pub fifths: i32,
+}
+
+impl KeySignature {
+    pub fn new(fifths: i32) -> Self {
+        KeySignature {
+            fifths: fifths.clamp(-7, 7),
+        }
+    }
+
+    /// The accidental the signature applies to each letter, indexed like `LETTERS`.
+    pub fn accidentals(self) -> [i32; 7] {
+        let mut accidentals = [0; 7];
+        if self.fifths > 0 {
+            for &letter in SHARP_ORDER.iter().take(self.fifths as usize) {
+                accidentals[letter] = 1;
+            }
+        } else {
+            for &letter in FLAT_ORDER.iter().take(-self.fifths as usize) {
+                accidentals[letter] = -1;
+            }
+        }
+        accidentals
+    }
+
+    /// Whether `note` is one of the seven notes of the key.
+    pub fn contains(self, note: Note) -> bool {
+        let accidentals = self.accidentals();
+        (0..7).any(|letter| {
+            (LETTER_SEMITONES[letter] + accidentals[letter]).rem_euclid(12) == note.pitch_class()
+        })
+    }
+
+    /// Letter index and accidental for a pitch class. Notes outside the key are
+    /// natural where possible, otherwise sharp in sharp keys and flat in flat keys.
+    pub fn spell(self, pitch_class: i32) -> (usize, i32) {
+        let accidentals = self.accidentals();
+        let with_accidental = |accidental: &dyn Fn(usize) -> i32| {
+            (0..7)
+                .find(|&letter| {
+                    (LETTER_SEMITONES[letter] + accidental(letter)).rem_euclid(12) == pitch_class
+                })
+                .map(|letter| (letter, accidental(letter)))
+        };
+        let chromatic = if self.fifths < 0 { -1 } else { 1 };
+
+        with_accidental(&|letter| accidentals[letter])
+            .or_else(|| with_accidental(&|_| 0))
+            .or_else(|| with_accidental(&|_| chromatic))
+            .unwrap_or_default()
+    }
+
+    /// The signature that fits the most of `notes`, preferring fewer accidentals on ties.
+    pub fn guess<'a>(notes: impl IntoIterator<Item = &'a Note> + Clone) -> KeySignature {
+        (0..=6)
+            .flat_map(|fifths| [fifths, -fifths])
+            .map(KeySignature::new)
+            .min_by_key(|key| {
+                notes
+                    .clone()
+                    .into_iter()
+                    .filter(|&&note| !key.contains(note))
+                    .count()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transposes_within_the_midi_range() {
+        assert_eq!(Note(60).transpose(7), Some(Note(67)));
+        assert_eq!(Note(60).transpose(-60), Some(Note(0)));
+        assert_eq!(Note(120).transpose(7), Some(Note(127)));
+        assert_eq!(Note(0).transpose(-1), None);
+        assert_eq!(Note(120).transpose(8), None);
+    }
+
+    #[test]
+    fn names_notes_for_the_key() {
+        assert_eq!(Note(60).name(), "C4");
+        assert_eq!(Note(61).name(), "C#4");
+        assert_eq!(Note(69).to_string(), "A4");
+        assert_eq!(Note(54).name_in(KeySignature::new(2)), "F#3");
+        assert_eq!(Note(54).name_in(KeySignature::new(-5)), "Gb3");
+        assert_eq!(Note(0).name(), "C-1");
+
+        // B# and Cb are named for the octave of their letter
+        assert_eq!(Note(60).name_in(KeySignature::new(7)), "B#3");
+        assert_eq!(Note(59).name_in(KeySignature::new(-7)), "Cb4");
+    }
+
+    #[test]
+    fn spells_notes_in_and_out_of_the_key() {
+        let letter = |letter| letter_index(letter).unwrap();
+        let d_major = KeySignature::new(2);
+        assert_eq!(d_major.spell(6), (letter('F'), 1));
+        assert_eq!(d_major.spell(5), (letter('F'), 0));
+        assert_eq!(d_major.spell(3), (letter('D'), 1));
+
+        let b_flat_major = KeySignature::new(-2);
+        assert_eq!(b_flat_major.spell(10), (letter('B'), -1));
+        assert_eq!(b_flat_major.spell(11), (letter('B'), 0));
+        assert_eq!(b_flat_major.spell(1), (letter('D'), -1));
+    }
+
+    #[test]
+    fn guesses_the_key_with_the_fewest_notes_outside_it() {
+        let notes = |pitches: &[usize]| pitches.iter().copied().map(Note).collect::<Vec<_>>();
+
+        // D major scale
+        let d_major = notes(&[62, 64, 66, 67, 69, 71, 73]);
+        assert_eq!(KeySignature::guess(&d_major), KeySignature::new(2));
+        // F major scale
+        let f_major = notes(&[65, 67, 69, 70, 72, 74, 76]);
+        assert_eq!(KeySignature::guess(&f_major), KeySignature::new(-1));
+        // C, E and G fit several keys; the one with no accidentals wins
+        assert_eq!(
+            KeySignature::guess(&notes(&[60, 64, 67])),
+            KeySignature::new(0)
+        );
+        assert_eq!(KeySignature::guess(&[]), KeySignature::new(0));
+    }
+}
@@ -0,0 +1,293 @@
+// render.rs
+use super::{
+    song_from_abc, song_from_midi, song_from_ron, AbcError, CurrentBPM, Instrument,
+    MidiImportError, Note, Song, SongLoaderError, NOTES, SAMPLE_RATE,
+};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const USAGE: &str = "usage: the_last_hymn render <song> <out.wav> [--bpm <bpm>] [--instrument <name>] [--transpose <semitones>] [--assets <dir>]";
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("could not read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("could not read or write WAV data: {0}")]
+    Wav(#[from] hound::Error),
+    #[error("unknown song format: {0}")]
+    UnknownFormat(PathBuf),
+    #[error(transparent)]
+    Song(#[from] SongLoaderError),
+    #[error(transparent)]
+    Midi(#[from] MidiImportError),
+    #[error(transparent)]
+    Abc(#[from] AbcError),
+}
+
+/// Reads a `.song.ron`, `.mid`/`.midi` or `.abc` file from disk.
+pub fn read_song(path: &Path) -> Result<Song, RenderError> {
+    let bytes = std::fs::read(path).map_err(|error| RenderError::Io(path.to_owned(), error))?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if name.ends_with(".song.ron") {
+        Ok(song_from_ron(&bytes)?)
+    } else if name.ends_with(".mid") || name.ends_with(".midi") {
+        let title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(song_from_midi(&title, &bytes)?)
+    } else if name.ends_with(".abc") {
+        let source = String::from_utf8_lossy(&bytes);
+        Ok(song_from_abc(&source)?)
+    } else {
+        Err(RenderError::UnknownFormat(path.to_owned()))
+    }
+}
+
+/// Reads a WAV sample as mono audio at `SAMPLE_RATE`.
+fn read_sample(path: &Path) -> Result<Vec<f32>, RenderError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    let mono: Vec<f32> = interleaved
+        .chunks(spec.channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+
+    if spec.sample_rate == SAMPLE_RATE {
+        return Ok(mono);
+    }
+
+    // Linear resampling is plenty for previewing transcriptions
+    let step = spec.sample_rate as f32 / SAMPLE_RATE as f32;
+    let length = (mono.len() as f32 / step) as usize;
+    Ok((0..length)
+        .map(|i| {
+            let position = i as f32 * step;
+            let index = position as usize;
+            let next = mono.get(index + 1).copied().unwrap_or(0.0);
+            mono[index] + (next - mono[index]) * position.fract()
+        })
+        .collect())
+}
+
+/// Mixes every note of `song`, harmony included, into mono samples at
+/// `SAMPLE_RATE`. The song's tempo map is scaled so that it starts at `bpm`.
+/// Sample instruments load their WAV files from `assets`; notes outside their range are skipped.
+pub fn render_song(
+    song: &Song,
+    bpm: &CurrentBPM,
+    instrument: &Instrument,
+    assets: &Path,
+) -> Result<Vec<f32>, RenderError> {
+    let tempo_scale = bpm.bpm / song.bpm;
+    let mut sounds: HashMap<Note, Vec<f32>> = HashMap::new();
+    let mut mix: Vec<f32> = vec![];
+    let mut end = 0;
+
+    for voice in [&song.notes, &song.harmony] {
+        let mut cursor = 0.0;
+        let mut beat = 0.0;
+
+        for step in voice {
+            for &note in &step.notes {
+                let sound = match sounds.entry(note) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(match instrument {
+                        Instrument::Samples => match note.sample_index() {
+                            Some(index) => read_sample(&assets.join(NOTES[index]))?,
+                            None => vec![],
+                        },
+                        Instrument::Synth(voice) => voice.render(note, SAMPLE_RATE),
+                    }),
+                };
+
+                let start = (cursor * SAMPLE_RATE as f32) as usize;
+                if mix.len() < start + sound.len() {
+                    mix.resize(start + sound.len(), 0.0);
+                }
+                for (out, sample) in mix[start..].iter_mut().zip(sound.iter()) {
+                    *out += sample;
+                }
+            }
+
+            let tempo = CurrentBPM::new(song.bpm_at(beat) * tempo_scale);
+            cursor += tempo.seconds(step.beats);
+            beat += step.beats;
+        }
+
+        end = end.max((cursor * SAMPLE_RATE as f32) as usize);
+    }
+
+    if mix.len() < end {
+        mix.resize(end, 0.0);
+    }
+
+    // Overlapping tails can clip, so scale the whole mix down if needed
+    let peak = mix
+        .iter()
+        .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+    if peak > 1.0 {
+        mix.iter_mut().for_each(|sample| *sample /= peak);
+    }
+
+    Ok(mix)
+}
+
+/// Writes mono samples as a 16-bit PCM WAV file.
+pub fn write_wav(path: &Path, samples: &[f32]) -> Result<(), RenderError> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+
+    for sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+
+    Ok(())
+}
+
+/// Runs `the_last_hymn render ...` with the arguments after `render`.
+pub fn render_command(args: &[String]) -> Result<(), RenderError> {
+    let usage = || RenderError::Usage(USAGE.to_string());
+    let mut paths = vec![];
+    let mut bpm = None;
+    let mut instrument = Instrument::Samples;
+    let mut transpose = None;
+    let mut assets = PathBuf::from("assets");
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bpm" => {
+                let value = args.next().ok_or_else(usage)?;
+                let value = value.parse::<f32>().map_err(|_| usage())?;
+                if !(value.is_finite() && value > 0.0) {
+                    return Err(RenderError::Usage(format!(
+                        "--bpm must be a positive number, got {value}"
+                    )));
+                }
+                bpm = Some(value);
+            }
+            "--instrument" => {
+                let name = args.next().ok_or_else(usage)?;
+                instrument = Instrument::from_name(name)
+                    .ok_or_else(|| RenderError::Usage(format!("unknown instrument {name:?}")))?;
+            }
+            "--transpose" => {
+                let value = args.next().ok_or_else(usage)?;
+                transpose = Some(value.parse::<i32>().map_err(|_| usage())?);
+            }
+            "--assets" => assets = PathBuf::from(args.next().ok_or_else(usage)?),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [song_path, out_path] = paths.as_slice() else {
+        return Err(usage());
+    };
+
+    let mut song = read_song(song_path)?;
+    // Like the game, fit the song to the samples unless told how far to move it
+    let (low, high) = Note::sample_range();
+    let transpose = transpose.unwrap_or_else(|| song.fit_to_range(low, high).unwrap_or(0));
+    if transpose != 0 {
+        song = song.transposed(transpose).ok_or_else(|| {
+            RenderError::Usage(format!("transposing by {transpose} leaves the MIDI range"))
+        })?;
+    }
+    let bpm = CurrentBPM::new(bpm.unwrap_or(song.bpm));
+    let samples = render_song(&song, &bpm, &instrument, &assets)?;
+    write_wav(out_path, &samples)?;
+
+    println!(
+        "Rendered {:?} at {} BPM with {} to {}",
+        song.title,
+        bpm.bpm,
+        instrument.name(),
+        out_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{Step, SynthVoice};
+
+    #[test]
+    fn renders_a_song_with_a_synth_voice() {
+        // Half a beat of A4, a rest, then a beat of A4 and E5, at two beats a second
+        let song = Song::new(
+            "t",
+            120.0,
+            vec![
+                Step::new(&[69], 0.5),
+                Step::rest(0.5),
+                Step::new(&[69, 76], 1.0),
+            ],
+        );
+        let instrument = Instrument::Synth(SynthVoice::chiptune());
+        let samples =
+            render_song(&song, &CurrentBPM::new(120.0), &instrument, Path::new("")).unwrap();
+
+        // The song runs for a second, past the tails of its notes
+        assert_eq!(samples.len(), SAMPLE_RATE as usize);
+        let chord_start = SAMPLE_RATE as usize / 2;
+        let tail = (SynthVoice::chiptune().length() * SAMPLE_RATE as f32).ceil() as usize;
+        assert!(samples[tail..chord_start]
+            .iter()
+            .all(|&sample| sample == 0.0));
+        // The chord is the loudest part, and quiet enough not to be scaled down
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 0.6982).abs() < 1e-4, "peak {peak}");
+        assert!(samples[..chord_start]
+            .iter()
+            .all(|sample| sample.abs() < peak));
+
+        let path =
+            std::env::temp_dir().join(format!("the_last_hymn_render_{}.wav", std::process::id()));
+        write_wav(&path, &samples).unwrap();
+        let reader = hound::WavReader::open(&path).unwrap();
+        let spec = reader.spec();
+        let written: Vec<i16> = reader.into_samples().collect::<Result<_, _>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.sample_rate, SAMPLE_RATE);
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(spec.sample_format, hound::SampleFormat::Int);
+        assert_eq!(written.len(), samples.len());
+        assert_eq!(
+            written.iter().map(|sample| sample.unsigned_abs()).max(),
+            Some(22878)
+        );
+    }
+}
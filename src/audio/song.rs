@@ -0,0 +1,372 @@
+// song.rs
+use super::{KeySignature, Note, BPM};
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Melody notes held at least this many beats end a phrase.
+pub const PHRASE_END_BEATS: f32 = 2.0;
+
+/// A tempo marker. With a `ramp` it becomes an accelerando or ritardando
+/// from the previous tempo, reaching `bpm` after `ramp` beats.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct TempoChange {
+    /// Position in the song, in beats from the start.
+    pub beat: f32,
+    pub bpm: f32,
+    #[serde(default)]
+    pub ramp: f32,
+}
+
+/// Notes that start together, with their length in beats. A step with no
+/// notes is a rest.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Step {
+    pub notes: Vec<Note>,
+    pub beats: f32,
+}
+
+impl Step {
+    /// Builds a step from MIDI pitches.
+    pub fn new(pitches: &[usize], beats: f32) -> Self {
+        Step {
+            notes: pitches.iter().copied().map(Note).collect(),
+            beats,
+        }
+    }
+
+    pub fn rest(beats: f32) -> Self {
+        Step {
+            notes: vec![],
+            beats,
+        }
+    }
+
+    pub fn is_rest(&self) -> bool {
+        self.notes.is_empty()
+    }
+}
+
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct Song {
+    pub title: String,
+    /// Starting tempo, in quarter-note beats per minute.
+    pub bpm: f32,
+    /// The melody, one step after another.
+    pub notes: Vec<Step>,
+    /// An optional second voice with its own rhythm, following the same tempo map.
+    pub harmony: Vec<Step>,
+    /// Tempo markers, sorted by position.
+    pub tempo_changes: Vec<TempoChange>,
+}
+
+impl Song {
+    pub fn new(title: impl Into<String>, bpm: f32, notes: Vec<Step>) -> Self {
+        Song {
+            title: title.into(),
+            bpm,
+            notes,
+            harmony: vec![],
+            tempo_changes: vec![],
+        }
+    }
+
+    pub fn with_harmony(mut self, harmony: Vec<Step>) -> Self {
+        self.harmony = harmony;
+        self
+    }
+
+    pub fn with_tempo_changes(mut self, mut tempo_changes: Vec<TempoChange>) -> Self {
+        tempo_changes.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+        self.tempo_changes = tempo_changes;
+        self
+    }
+
+    /// Position of the step at `index`, in beats from the start.
+    pub fn beat_at(&self, index: usize) -> f32 {
+        self.notes.iter().take(index).map(|step| step.beats).sum()
+    }
+
+    /// Whether the melody step at `index` closes a phrase: it is held for at
+    /// least `PHRASE_END_BEATS`, comes before a rest or ends the song.
+    pub fn ends_phrase(&self, index: usize) -> bool {
+        let Some(step) = self.notes.get(index) else {
+            return false;
+        };
+        step.beats >= PHRASE_END_BEATS
+            || self.notes.get(index + 1).is_none_or(|next| next.is_rest())
+    }
+
+    /// Harmony steps starting at or after `start` and before `end`, in beats.
+    pub fn harmony_between(&self, start: f32, end: f32) -> impl Iterator<Item = &Step> {
+        self.harmony
+            .iter()
+            .scan(0.0, |beat, step| {
+                let step_start = *beat;
+                *beat += step.beats;
+                Some((step_start, step))
+            })
+            .skip_while(move |&(beat, _)| beat < start)
+            .take_while(move |&(beat, _)| beat < end)
+            .map(|(_, step)| step)
+    }
+
+    /// The song moved by `semitones`, or `None` if a note would leave the MIDI range.
+    pub fn transposed(&self, semitones: i32) -> Option<Song> {
+        let transpose = |steps: &[Step]| {
+            steps
+                .iter()
+                .map(|step| {
+                    Some(Step {
+                        notes: step
+                            .notes
+                            .iter()
+                            .map(|note| note.transpose(semitones))
+                            .collect::<Option<_>>()?,
+                        beats: step.beats,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+
+        Some(Song {
+            notes: transpose(&self.notes)?,
+            harmony: transpose(&self.harmony)?,
+            ..self.clone()
+        })
+    }
+
+    /// The smallest transposition that puts every note between `low` and `high`,
+    /// preferring whole octaves so the song stays in its key. `None` if the song
+    /// spans more than the range.
+    pub fn fit_to_range(&self, low: Note, high: Note) -> Option<i32> {
+        let notes = self.distinct_notes();
+        let (Some(lowest), Some(highest)) = (notes.iter().min(), notes.iter().max()) else {
+            return Some(0);
+        };
+
+        let min_shift = low.0 as i32 - lowest.0 as i32;
+        let max_shift = high.0 as i32 - highest.0 as i32;
+        if min_shift > max_shift {
+            return None;
+        }
+
+        let octaves = (min_shift..=max_shift).filter(|shift| shift % 12 == 0);
+        octaves
+            .min_by_key(|shift| shift.abs())
+            .or_else(|| (min_shift..=max_shift).min_by_key(|shift| shift.abs()))
+    }
+
+    /// The key signature that best matches the song's notes.
+    pub fn key(&self) -> KeySignature {
+        KeySignature::guess(&self.distinct_notes())
+    }
+
+    /// Every distinct note in the melody and harmony.
+    pub fn distinct_notes(&self) -> Vec<Note> {
+        let mut notes = vec![];
+        for &note in self
+            .notes
+            .iter()
+            .chain(&self.harmony)
+            .flat_map(|step| &step.notes)
+        {
+            if !notes.contains(&note) {
+                notes.push(note);
+            }
+        }
+        notes
+    }
+
+    /// Checks that every tempo is positive and every step lasts a finite,
+    /// non-negative number of beats, so playback can turn them into durations.
+    pub fn check_timing(&self) -> Result<(), SongTimingError> {
+        let valid_bpm = |bpm: f32| bpm.is_finite() && bpm > 0.0;
+        if !valid_bpm(self.bpm) {
+            return Err(SongTimingError::Tempo(self.bpm));
+        }
+        for change in &self.tempo_changes {
+            if !valid_bpm(change.bpm) {
+                return Err(SongTimingError::Tempo(change.bpm));
+            }
+            if !(change.beat.is_finite() && change.ramp.is_finite() && change.ramp >= 0.0) {
+                return Err(SongTimingError::TempoChange(change.beat));
+            }
+        }
+        for (index, step) in self.notes.iter().chain(&self.harmony).enumerate() {
+            if !(step.beats.is_finite() && step.beats >= 0.0) {
+                return Err(SongTimingError::StepLength {
+                    index,
+                    beats: step.beats,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The tempo at `beat`, following any tempo changes and ramps before it.
+    pub fn bpm_at(&self, beat: f32) -> f32 {
+        let mut bpm = self.bpm;
+
+        for change in &self.tempo_changes {
+            if beat < change.beat {
+                break;
+            }
+            let progress = if change.ramp > 0.0 {
+                ((beat - change.beat) / change.ramp).min(1.0)
+            } else {
+                1.0
+            };
+            bpm += (change.bpm - bpm) * progress;
+        }
+
+        bpm
+    }
+}
+
+/// On-disk layout of a `.song.ron` file.
+#[derive(Deserialize)]
+struct SongFile {
+    title: String,
+    #[serde(default = "default_bpm")]
+    bpm: f32,
+    notes: Vec<(Vec<usize>, f32)>,
+    #[serde(default)]
+    harmony: Vec<(Vec<usize>, f32)>,
+    #[serde(default)]
+    tempo_changes: Vec<TempoChange>,
+}
+
+fn default_bpm() -> f32 {
+    BPM
+}
+
+/// Parses the contents of a `.song.ron` file.
+pub fn song_from_ron(bytes: &[u8]) -> Result<Song, SongLoaderError> {
+    let file: SongFile = ron::de::from_bytes(bytes)?;
+    let steps = |steps: Vec<(Vec<usize>, f32)>| {
+        steps
+            .iter()
+            .map(|(pitches, beats)| Step::new(pitches, *beats))
+            .collect()
+    };
+
+    let song = Song::new(file.title, file.bpm, steps(file.notes))
+        .with_harmony(steps(file.harmony))
+        .with_tempo_changes(file.tempo_changes);
+    song.check_timing()?;
+    Ok(song)
+}
+
+/// A tempo or length that playback can't turn into a duration.
+#[derive(Clone, Copy, Debug, PartialEq, Error)]
+pub enum SongTimingError {
+    #[error("tempo {0} bpm is not a positive number")]
+    Tempo(f32),
+    #[error("tempo change at beat {0} has an invalid position or ramp")]
+    TempoChange(f32),
+    /// `index` counts the melody's steps, then the harmony's.
+    #[error("step {index} lasts {beats} beats")]
+    StepLength { index: usize, beats: f32 },
+}
+
+/// The song the player is currently restoring. Point this at another handle to swap songs.
+#[derive(Resource)]
+pub struct CurrentSong(pub Handle<Song>);
+
+/// Sent whenever `Player.current_song` is replaced by a newly loaded song.
+#[derive(Event)]
+pub struct SongChanged;
+
+#[derive(Default)]
+pub struct SongLoader;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum SongLoaderError {
+    #[error("could not read song file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse song file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid song timing: {0}")]
+    Timing(#[from] SongTimingError),
+}
+
+impl AssetLoader for SongLoader {
+    type Asset = Song;
+    type Settings = ();
+    type Error = SongLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            song_from_ron(&bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["song.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{song_from_abc, AbcError};
+
+    #[test]
+    fn rejects_tempos_that_are_not_positive() {
+        for bpm in ["0.0", "-80.0", "inf"] {
+            let ron = format!("(title: \"t\", bpm: {bpm}, notes: [([60], 1.0)])");
+            assert!(matches!(
+                song_from_ron(ron.as_bytes()),
+                Err(SongLoaderError::Timing(SongTimingError::Tempo(_)))
+            ));
+        }
+
+        let ron = "(title: \"t\", notes: [([60], 1.0)], tempo_changes: [(beat: 1.0, bpm: 0.0)])";
+        assert!(matches!(
+            song_from_ron(ron.as_bytes()),
+            Err(SongLoaderError::Timing(SongTimingError::Tempo(_)))
+        ));
+
+        for tempo in ["Q:0", "Q:1/4=0"] {
+            let abc = format!("X:1\n{tempo}\nK:C\nCDE\n");
+            assert!(matches!(
+                song_from_abc(&abc),
+                Err(AbcError::Timing(SongTimingError::Tempo(_)))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_negative_step_lengths() {
+        let ron = "(title: \"t\", notes: [([60], 1.0), ([62], -0.5)])";
+        assert!(matches!(
+            song_from_ron(ron.as_bytes()),
+            Err(SongLoaderError::Timing(SongTimingError::StepLength {
+                index: 1,
+                ..
+            }))
+        ));
+
+        let ron = "(title: \"t\", notes: [([60], 1.0)], harmony: [([55], inf)])";
+        assert!(matches!(
+            song_from_ron(ron.as_bytes()),
+            Err(SongLoaderError::Timing(SongTimingError::StepLength {
+                index: 1,
+                ..
+            }))
+        ));
+    }
+}
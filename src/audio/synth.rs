@@ -0,0 +1,312 @@
+// synth.rs
+use super::Note;
+use bevy::prelude::*;
+use bevy_kira_audio::AudioSource;
+use kira::dsp::Frame;
+use kira::sound::static_sound::{StaticSoundData, StaticSoundSettings};
+use std::f32::consts::TAU;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+}
+
+impl Waveform {
+    /// Samples the waveform at `phase`, measured in cycles.
+    pub fn sample(self, phase: f32) -> f32 {
+        let phase = phase.fract();
+        match self {
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Saw => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+/// An oscillator tuned to a multiple of the note's frequency.
+#[derive(Clone, Copy, Debug)]
+pub struct Oscillator {
+    pub waveform: Waveform,
+    pub ratio: f32,
+    pub gain: f32,
+}
+
+/// Attack, decay and release in seconds, sustain as a level from 0 to 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Envelope {
+    /// Amplitude `t` seconds after the note starts, for a note held for `hold` seconds.
+    pub fn amplitude(&self, t: f32, hold: f32) -> f32 {
+        let held = |t: f32| {
+            if t < self.attack {
+                t / self.attack
+            } else if t < self.attack + self.decay {
+                1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+            } else {
+                self.sustain
+            }
+        };
+
+        if t < hold {
+            held(t)
+        } else {
+            held(hold) * (1.0 - (t - hold) / self.release).max(0.0)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SynthVoice {
+    pub name: &'static str,
+    pub oscillators: Vec<Oscillator>,
+    pub envelope: Envelope,
+    /// How long each note is held before its release starts, in seconds.
+    pub hold: f32,
+}
+
+impl SynthVoice {
+    pub fn organ() -> Self {
+        SynthVoice {
+            name: "Organ",
+            oscillators: vec![
+                Oscillator {
+                    waveform: Waveform::Sine,
+                    ratio: 1.0,
+                    gain: 0.6,
+                },
+                Oscillator {
+                    waveform: Waveform::Sine,
+                    ratio: 2.0,
+                    gain: 0.25,
+                },
+                Oscillator {
+                    waveform: Waveform::Triangle,
+                    ratio: 4.0,
+                    gain: 0.1,
+                },
+            ],
+            envelope: Envelope {
+                attack: 0.03,
+                decay: 0.2,
+                sustain: 0.7,
+                release: 0.4,
+            },
+            hold: 0.8,
+        }
+    }
+
+    pub fn bell() -> Self {
+        SynthVoice {
+            name: "Bell",
+            oscillators: vec![
+                Oscillator {
+                    waveform: Waveform::Sine,
+                    ratio: 1.0,
+                    gain: 0.6,
+                },
+                Oscillator {
+                    waveform: Waveform::Sine,
+                    ratio: 2.76,
+                    gain: 0.2,
+                },
+                Oscillator {
+                    waveform: Waveform::Sine,
+                    ratio: 5.4,
+                    gain: 0.1,
+                },
+            ],
+            envelope: Envelope {
+                attack: 0.005,
+                decay: 0.6,
+                sustain: 0.2,
+                release: 0.8,
+            },
+            hold: 0.4,
+        }
+    }
+
+    pub fn chiptune() -> Self {
+        SynthVoice {
+            name: "Chiptune",
+            oscillators: vec![
+                Oscillator {
+                    waveform: Waveform::Square,
+                    ratio: 1.0,
+                    gain: 0.3,
+                },
+                Oscillator {
+                    waveform: Waveform::Saw,
+                    ratio: 0.5,
+                    gain: 0.15,
+                },
+            ],
+            envelope: Envelope {
+                attack: 0.005,
+                decay: 0.1,
+                sustain: 0.5,
+                release: 0.1,
+            },
+            hold: 0.3,
+        }
+    }
+
+    /// Total length of a rendered note, including its release.
+    pub fn length(&self) -> f32 {
+        self.hold + self.envelope.release
+    }
+
+    /// Renders one note as mono samples.
+    pub fn render(&self, note: Note, sample_rate: u32) -> Vec<f32> {
+        let frequency = note.frequency();
+        let samples = (self.length() * sample_rate as f32).ceil() as usize;
+
+        (0..samples)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let signal: f32 = self
+                    .oscillators
+                    .iter()
+                    .map(|osc| osc.waveform.sample(frequency * osc.ratio * t) * osc.gain)
+                    .sum();
+                signal * self.envelope.amplitude(t, self.hold)
+            })
+            .collect()
+    }
+
+    /// Renders one note as static sound data that can be played like a sample.
+    pub fn sound(&self, note: Note) -> AudioSource {
+        audio_source(self.render(note, SAMPLE_RATE))
+    }
+
+    /// Renders `notes` held together for `seconds`, ignoring the envelope. Each
+    /// oscillator is nudged to a whole number of cycles so the result loops
+    /// without a click.
+    pub fn render_loop(&self, notes: &[Note], seconds: f32, sample_rate: u32) -> Vec<f32> {
+        let samples = (seconds * sample_rate as f32).round() as usize;
+        let partials: Vec<(Waveform, f32, f32)> = notes
+            .iter()
+            .flat_map(|note| {
+                self.oscillators.iter().map(move |osc| {
+                    let cycles = (note.frequency() * osc.ratio * seconds).round();
+                    (osc.waveform, cycles / seconds, osc.gain)
+                })
+            })
+            .collect();
+        let scale = 1.0 / notes.len().max(1) as f32;
+
+        (0..samples)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let signal: f32 = partials
+                    .iter()
+                    .map(|&(waveform, frequency, gain)| waveform.sample(frequency * t) * gain)
+                    .sum();
+                signal * scale
+            })
+            .collect()
+    }
+}
+
+/// A one-pole low-pass filter, for darker versions of rendered sounds. The
+/// filter runs over `samples` twice so looped sounds start from a settled state.
+pub fn low_pass(samples: &mut [f32], cutoff: f32, sample_rate: u32) {
+    let alpha = 1.0 - (-TAU * cutoff / sample_rate as f32).exp();
+    let mut state = 0.0;
+
+    for sample in samples.iter() {
+        state += alpha * (sample - state);
+    }
+    for sample in samples.iter_mut() {
+        state += alpha * (*sample - state);
+        *sample = state;
+    }
+}
+
+/// Wraps mono samples at `SAMPLE_RATE` as static sound data.
+pub fn audio_source(samples: Vec<f32>) -> AudioSource {
+    AudioSource {
+        sound: StaticSoundData {
+            sample_rate: SAMPLE_RATE,
+            frames: samples.into_iter().map(Frame::from_mono).collect(),
+            settings: StaticSoundSettings::default(),
+        },
+    }
+}
+
+/// The instrument notes are played with.
+#[derive(Resource, Clone, Debug, Default)]
+pub enum Instrument {
+    /// The WAV files listed in `NOTES`. Only covers C3 to D4.
+    #[default]
+    Samples,
+    Synth(SynthVoice),
+}
+
+impl Instrument {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instrument::Samples => "Samples",
+            Instrument::Synth(voice) => voice.name,
+        }
+    }
+
+    /// Looks up an instrument by its case-insensitive name.
+    pub fn from_name(name: &str) -> Option<Instrument> {
+        let mut instrument = Instrument::Samples;
+        loop {
+            if instrument.name().eq_ignore_ascii_case(name) {
+                return Some(instrument);
+            }
+            instrument = instrument.next();
+            if let Instrument::Samples = instrument {
+                return None;
+            }
+        }
+    }
+
+    /// The instrument after this one when cycling through them.
+    pub fn next(&self) -> Instrument {
+        let voices = [
+            SynthVoice::organ(),
+            SynthVoice::bell(),
+            SynthVoice::chiptune(),
+        ];
+        let next = match self {
+            Instrument::Samples => 0,
+            Instrument::Synth(voice) => voices
+                .iter()
+                .position(|v| v.name == voice.name)
+                .map_or(0, |i| i + 1),
+        };
+
+        voices
+            .into_iter()
+            .nth(next)
+            .map_or(Instrument::Samples, Instrument::Synth)
+    }
+}
+
+pub fn cycle_instrument(keyboard: Res<ButtonInput<KeyCode>>, mut instrument: ResMut<Instrument>) {
+    if keyboard.just_pressed(KeyCode::KeyI) {
+        *instrument = instrument.next();
+        info!("Instrument: {}", instrument.name());
+    }
+}
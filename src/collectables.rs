@@ -1,12 +1,13 @@
-use crate::audio::Note;
+use crate::audio::{
+    BeatClock, BeatEvent, Channel, MixerSettings, Note, NoteSounds, SfxChannel, SongChanged,
+    SynthVoice,
+};
 use crate::player::Player;
 
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use bevy_kira_audio::{AudioChannel, AudioControl, AudioSource};
 use bevy_rapier2d::prelude::*;
-// Import rand for random number generation
-use crate::tiles::{Tile, TileType};
-use rand::Rng;
-use std::collections::HashSet; // Import Tile and TileType
 
 // collectables.rs
 
@@ -17,33 +18,78 @@ pub fn spawn_collectable_notes(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     player: Res<Player>,
-    tile_query: Query<(&Transform, &Tile)>,
+    mut song_changed: EventReader<SongChanged>,
+    collectable_notes_query: Query<Entity, With<CollectableNote>>,
 ) {
+    if song_changed.read().last().is_none() {
+        return;
+    }
+
+    // Clear out the pickups of the previous song
+    for entity in collectable_notes_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // Chords and the harmony voice add their own notes to collect
     let notes_to_collect: Vec<Note> = player
         .current_song
-        .0
-        .iter()
-        .filter_map(|&(note, _)| note)
-        .collect::<HashSet<_>>()
+        .distinct_notes()
         .into_iter()
+        .filter(|note| !player.current_notes.contains(note))
         .collect();
 
+    let key = player.current_song.key();
+
     for (i, note) in notes_to_collect.iter().enumerate() {
-        commands.spawn((
-            SpriteBundle {
-                texture: asset_server.load("tile_0029.png"),
-                sprite: Sprite {
-                    custom_size: Some(Vec2::new(8.0, 8.0)),
+        commands
+            .spawn((
+                SpriteBundle {
+                    texture: asset_server.load("tile_0029.png"),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(8.0, 8.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz((i as f32) * 50.0, 0.0, 1.0),
                     ..default()
                 },
-                transform: Transform::from_xyz((i as f32) * 50.0, 0.0, 1.0),
-                ..default()
-            },
-            RigidBody::Fixed,
-            Collider::ball(4.0),
-            CollectableNote,
-            *note,
-        ));
+                RigidBody::Fixed,
+                Collider::ball(4.0),
+                CollectableNote,
+                *note,
+            ))
+            .with_children(|parent| {
+                // Label the pickup with its note name, spelled for the song's key
+                parent.spawn(Text2dBundle {
+                    text: Text::from_section(
+                        note.name_in(key),
+                        TextStyle {
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ),
+                    transform: Transform::from_xyz(0.0, 8.0, 1.0).with_scale(Vec3::splat(0.25)),
+                    ..default()
+                });
+            });
+    }
+}
+
+/// Chimes picked up notes on the SFX channel.
+#[derive(SystemParam)]
+pub struct PickupChime<'w> {
+    sfx_channel: Res<'w, AudioChannel<SfxChannel>>,
+    mixer: Res<'w, MixerSettings>,
+    audio_sources: ResMut<'w, Assets<AudioSource>>,
+}
+
+impl PickupChime<'_> {
+    /// Rings a bell at the pitch of `note`.
+    pub fn play(&mut self, note: Note) {
+        let chime = self.audio_sources.add(SynthVoice::bell().sound(note));
+        self.sfx_channel
+            .play(chime)
+            .with_volume(self.mixer.volume(Channel::Sfx));
     }
 }
 
@@ -53,16 +99,73 @@ pub fn collect_notes(
     mut collectable_notes_query: Query<(Entity, &Transform, &Note), With<CollectableNote>>,
     player_query: Query<(Entity, &Transform, &Collider), With<Player>>,
     rapier_context: Res<RapierContext>,
+    mut pickup_chime: PickupChime,
 ) {
     if let Ok((player_entity, _, _)) = player_query.get_single() {
         for (note_entity, _, note) in collectable_notes_query.iter_mut() {
             if let Some(contact_pair) = rapier_context.contact_pair(player_entity, note_entity) {
-                info!("Picked up {:?}", *note);
+                info!("Picked up {}", note);
                 if contact_pair.raw.has_any_active_contact {
-                    player.current_notes.push(*note);
-                    commands.entity(note_entity).despawn();
+                    let harmony_was_unlocked = player.harmony_unlocked();
+                    if !player.current_notes.contains(note) {
+                        player.current_notes.push(*note);
+                    }
+                    commands.entity(note_entity).despawn_recursive();
+
+                    pickup_chime.play(*note);
+
+                    if !harmony_was_unlocked && player.harmony_unlocked() {
+                        info!("Harmony unlocked");
+                    }
                 }
             }
         }
     }
 }
+
+/// Uncollected notes are heard within this many pixels of the player.
+const HEARING_RADIUS: f32 = 400.0;
+/// Volume of a pickup right next to the player, well under a played note.
+const EMITTER_VOLUME: f64 = 0.6;
+
+/// Each uncollected note softly sounds its own pitch once a bar, panned and
+/// faded by where it is relative to the player, so missing notes can be found by ear.
+pub fn emit_note_sounds(
+    mut beat_events: EventReader<BeatEvent>,
+    beat_clock: Res<BeatClock>,
+    collectable_notes_query: Query<(&Transform, &Note), With<CollectableNote>>,
+    player_query: Query<&Transform, With<Player>>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+    mixer: Res<MixerSettings>,
+    mut note_sounds: NoteSounds,
+) {
+    let Some(beat) = beat_events.read().last() else {
+        return;
+    };
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+
+    for (transform, note) in collectable_notes_query.iter() {
+        // Spread the pickups over the bar instead of sounding them all at once
+        if note.0 as u32 % beat_clock.beats_per_bar != beat.beat_in_bar {
+            continue;
+        }
+
+        let offset = (transform.translation - player_transform.translation).truncate();
+        let closeness = 1.0 - offset.length() / HEARING_RADIUS;
+        if closeness <= 0.0 {
+            continue;
+        }
+
+        if let Some(note_handle) = note_sounds.handle(*note) {
+            let panning = 0.5 + 0.5 * (offset.x / HEARING_RADIUS).clamp(-1.0, 1.0);
+            sfx_channel
+                .play(note_handle)
+                .with_volume(
+                    EMITTER_VOLUME * (closeness * closeness) as f64 * mixer.volume(Channel::Sfx),
+                )
+                .with_panning(panning as f64);
+        }
+    }
+}
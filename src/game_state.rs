@@ -0,0 +1,209 @@
+// game_state.rs
+use bevy::prelude::*;
+
+use crate::audio::{BeatEvent, SongChanged};
+use crate::player::Player;
+use crate::rhythm::RhythmScore;
+use crate::tiles::{
+    ChunkTiles, CorruptionRng, CorruptionTimer, LoadedChunks, PendingPurification, TileMap,
+    TileRegistry, TileType, WorldSeed, CORRUPTION_STREAM, SPAWN_TILE, TILE_SIZE,
+};
+
+/// Fraction of the loaded tiles that, once corrupted, ends the run.
+const DEFEAT_CORRUPTION: f32 = 0.6;
+
+/// Where the game is. Gameplay systems only run while `Playing`.
+#[derive(States, Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GameState {
+    /// Shown at launch and between runs.
+    #[default]
+    Title,
+    Playing,
+    Paused,
+    /// Every note of the song was collected and the whole song was played.
+    Victory,
+    /// The corruption took the tile under the player, or too much of the stage.
+    Defeat,
+}
+
+impl GameState {
+    /// Heading and hint of the screen shown over the stage, if any.
+    fn screen_text(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            GameState::Title => Some(("The Last Hymn", "Press Enter to begin")),
+            GameState::Playing => None,
+            GameState::Paused => Some(("Paused", "Press Esc to resume")),
+            GameState::Victory => Some(("The hymn is restored", "Press Enter to continue")),
+            GameState::Defeat => Some(("The corruption has taken hold", "Press Enter to continue")),
+        }
+    }
+}
+
+/// Sent when the song is played from its first step to its last without a
+/// note of the melody missing or missed.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SongPlayedThrough;
+
+#[derive(Component)]
+pub struct StateScreen;
+
+/// Swaps the screen shown over the stage for the one of the new state.
+pub fn show_state_screen(
+    mut commands: Commands,
+    state: Res<State<GameState>>,
+    screen_query: Query<Entity, With<StateScreen>>,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let Some((heading, hint)) = state.get().screen_text() else {
+        return;
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(16.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            StateScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                heading,
+                TextStyle {
+                    font_size: 48.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+            parent.spawn(TextBundle::from_section(
+                hint,
+                TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+        });
+}
+
+/// Enter starts a run and leaves the end screens; Esc pauses and resumes.
+pub fn handle_state_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let enter = keyboard.just_pressed(KeyCode::Enter);
+    let escape = keyboard.just_pressed(KeyCode::Escape);
+
+    match state.get() {
+        GameState::Title if enter => next_state.set(GameState::Playing),
+        GameState::Playing if escape => next_state.set(GameState::Paused),
+        GameState::Paused if escape => next_state.set(GameState::Playing),
+        GameState::Victory | GameState::Defeat if enter => next_state.set(GameState::Title),
+        _ => {}
+    }
+}
+
+/// The run is won once the song is played through with every one of its
+/// notes collected, chords and harmony included.
+pub fn check_victory(
+    mut song_played: EventReader<SongPlayedThrough>,
+    player: Res<Player>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if song_played.read().last().is_none() {
+        return;
+    }
+
+    let song_notes = player.current_song.distinct_notes();
+    if !song_notes.is_empty()
+        && song_notes
+            .iter()
+            .all(|note| player.current_notes.contains(note))
+    {
+        info!("Victory");
+        next_state.set(GameState::Victory);
+    }
+}
+
+/// Once a beat, ends the run if the tile under the player has reached the
+/// last stage of corruption or `DEFEAT_CORRUPTION` of the loaded tiles are corrupted.
+pub fn check_defeat(
+    mut beat_events: EventReader<BeatEvent>,
+    tile_map: Res<TileMap>,
+    tile_query: Query<&TileType>,
+    loaded_chunks: Res<LoadedChunks>,
+    registry: Res<TileRegistry>,
+    player_query: Query<&Transform, With<Player>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if beat_events.read().last().is_none() || tile_map.tiles.is_empty() {
+        return;
+    }
+
+    let fully_corrupted = registry.corruption_stage(registry.max_corruption_stage());
+    let reached_player = player_query.get_single().is_ok_and(|transform| {
+        let tile = (transform.translation.truncate() / TILE_SIZE)
+            .round()
+            .as_ivec2();
+        tile_map
+            .tiles
+            .get(&(tile.x, tile.y))
+            .and_then(|entity| tile_query.get(*entity).ok())
+            .is_some_and(|tile_type| *tile_type == fully_corrupted)
+    });
+
+    let corrupted = loaded_chunks
+        .chunks
+        .keys()
+        .filter_map(|chunk| loaded_chunks.corruption_tiles.get(chunk))
+        .map(|tiles| tiles.len())
+        .sum::<usize>();
+    let corrupted_fraction = corrupted as f32 / tile_map.tiles.len() as f32;
+
+    if reached_player || corrupted_fraction >= DEFEAT_CORRUPTION {
+        info!("Defeat ({:.0}% corrupted)", corrupted_fraction * 100.0);
+        next_state.set(GameState::Defeat);
+    }
+}
+
+/// Puts the stage and the player back as they were at launch once a run ends.
+/// The corruption is seeded again from the `WorldSeed` as chunks reload.
+pub fn reset_run(
+    mut commands: Commands,
+    seed: Res<WorldSeed>,
+    mut player: ResMut<Player>,
+    mut player_query: Query<&mut Transform, With<Player>>,
+    mut chunk_tiles: ChunkTiles,
+    mut song_changed: EventWriter<SongChanged>,
+) {
+    player.current_notes.clear();
+    player.note_index = 0;
+    player.next_step = None;
+    if let Ok(mut transform) = player_query.get_single_mut() {
+        transform.translation = (SPAWN_TILE.as_vec2() * TILE_SIZE).extend(transform.translation.z);
+    }
+
+    chunk_tiles.unload_all(&mut commands);
+    chunk_tiles.loaded_chunks.corruption_tiles.clear();
+
+    commands.insert_resource(CorruptionTimer::default());
+    commands.insert_resource(CorruptionRng(seed.rng(CORRUPTION_STREAM)));
+    commands.insert_resource(PendingPurification::default());
+    commands.insert_resource(RhythmScore::default());
+
+    // Brings the collectable notes back
+    song_changed.send(SongChanged);
+}
@@ -1,22 +1,47 @@
 // main.rs
 mod audio;
 mod collectables;
+mod game_state;
+mod music;
 mod player;
+mod rhythm;
 mod tiles;
 
 use bevy::prelude::*;
-use bevy_kira_audio::AudioPlugin;
+use bevy_ecs_tilemap::TilemapPlugin;
+use bevy_kira_audio::{AudioApp, AudioPlugin};
 use bevy_rapier2d::prelude::*;
+use game_state::GameState;
 use std::collections::HashMap;
 use tiles::*;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        if let Err(error) = audio::render_command(&args[1..]) {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let seed = match tiles::WorldSeed::from_args(&args) {
+        Ok(seed) => seed,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+
     App::new()
         .add_plugins((
             DefaultPlugins,
             AudioPlugin,
+            TilemapPlugin,
             RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
         ))
+        .init_state::<GameState>()
+        .add_event::<game_state::SongPlayedThrough>()
         .insert_resource(RapierConfiguration {
             gravity: Vec2::ZERO,
             ..Default::default()
@@ -24,26 +49,168 @@ fn main() {
         .insert_resource(TileMap {
             tiles: HashMap::new(),
         })
-        .insert_resource(PotentiallyCorruptedTiles { tiles: vec![] })
-        .insert_resource(CorruptionTimer {
-            timer: Timer::from_seconds(10.0, TimerMode::Repeating),
-        })
+        .init_resource::<PotentiallyCorruptedTiles>()
+        .insert_resource(seed)
+        .init_resource::<BiomeTable>()
+        .insert_resource(StageNoise::new(seed))
+        .insert_resource(CorruptionRng(seed.rng(CORRUPTION_STREAM)))
+        .init_resource::<CorruptionTimer>()
+        .init_resource::<CorruptionRules>()
+        .add_event::<Purification>()
+        .init_resource::<PendingPurification>()
         .insert_resource(audio::CurrentBPM::default())
+        .init_resource::<audio::Instrument>()
+        .init_resource::<audio::BeatClock>()
+        .add_event::<audio::BeatEvent>()
+        .init_asset::<TileRegistry>()
+        .init_asset_loader::<TileLoader>()
+        .init_asset::<audio::Song>()
+        .init_asset_loader::<audio::SongLoader>()
+        .init_asset_loader::<audio::MidiLoader>()
+        .init_asset_loader::<audio::AbcLoader>()
+        .add_event::<audio::SongChanged>()
+        .init_resource::<rhythm::RhythmMode>()
+        .init_resource::<rhythm::RhythmScore>()
+        .add_event::<rhythm::NoteJudged>()
+        .init_resource::<music::MusicMood>()
+        .add_audio_channel::<audio::MusicChannel>()
+        .add_audio_channel::<audio::NotesChannel>()
+        .add_audio_channel::<audio::SfxChannel>()
+        .add_audio_channel::<audio::AmbienceChannel>()
+        .insert_resource(audio::MixerSettings::load())
+        .init_resource::<audio::Ducking>()
         .add_systems(
             Startup,
             (
-                tiles::setup_tiles.before(collectables::spawn_collectable_notes),
-                tiles::generate_stage.after(tiles::setup_tiles),
+                tiles::setup_tiles,
                 player::setup_player.after(tiles::setup_tiles),
                 audio::setup_audio,
-                collectables::spawn_collectable_notes.after(player::setup_player),
+                music::setup_music_layers,
+                rhythm::spawn_rhythm_hud,
             ),
         )
-        .add_systems(Update, tiles::corruption_system)
-        .add_systems(Update, player::player_movement)
-        .add_systems(Update, player::play_notes)
+        .add_systems(Update, audio::tick_beat_clock)
+        .add_systems(
+            Update,
+            (
+                game_state::handle_state_input,
+                game_state::show_state_screen.run_if(state_changed::<GameState>),
+                game_state::check_victory.run_if(in_state(GameState::Playing)),
+                game_state::check_defeat
+                    .after(tiles::corruption_system)
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(resource_exists::<TileRegistry>),
+            ),
+        )
+        .add_systems(OnEnter(GameState::Playing), rhythm::count_in_on_play)
+        .add_systems(OnExit(GameState::Victory), game_state::reset_run)
+        .add_systems(OnExit(GameState::Defeat), game_state::reset_run)
+        .add_systems(
+            Update,
+            tiles::corruption_system
+                .after(audio::tick_beat_clock)
+                .run_if(in_state(GameState::Playing))
+                .run_if(resource_exists::<TileRegistry>),
+        )
+        .add_systems(Update, player::apply_current_song)
+        .add_systems(
+            Update,
+            collectables::spawn_collectable_notes.after(player::apply_current_song),
+        )
+        .add_systems(Update, tiles::apply_tile_set)
+        .add_systems(
+            Update,
+            tiles::purify_tiles
+                .after(player::play_notes)
+                .after(rhythm::play_judged_notes)
+                .before(tiles::corruption_system)
+                .run_if(in_state(GameState::Playing))
+                .run_if(resource_exists::<TileRegistry>),
+        )
+        .add_systems(
+            Update,
+            (
+                tiles::stream_chunks.after(tiles::apply_tile_set),
+                player::player_movement
+                    .after(tiles::stream_chunks)
+                    .run_if(in_state(GameState::Playing)),
+            )
+                .run_if(resource_exists::<TileRegistry>),
+        )
+        .add_systems(Update, audio::cycle_instrument)
+        .add_systems(
+            Update,
+            audio::clear_synth_notes
+                .after(audio::cycle_instrument)
+                .before(player::play_notes)
+                .before(rhythm::play_judged_notes),
+        )
+        .add_systems(
+            Update,
+            player::play_notes
+                .after(audio::tick_beat_clock)
+                .run_if(in_state(GameState::Playing))
+                .run_if(not(rhythm::rhythm_mode_enabled)),
+        )
+        .add_systems(
+            Update,
+            rhythm::toggle_rhythm_mode
+                .after(player::apply_current_song)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                rhythm::judge_notes
+                    .after(rhythm::toggle_rhythm_mode)
+                    .after(audio::tick_beat_clock)
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(rhythm::rhythm_mode_enabled),
+                rhythm::play_judged_notes.after(rhythm::judge_notes),
+                rhythm::score_notes.after(rhythm::judge_notes),
+                rhythm::update_rhythm_hud.after(rhythm::score_notes),
+            ),
+        )
+        .add_systems(
+            Update,
+            tiles::corruption_rhythm_response
+                .after(rhythm::judge_notes)
+                .before(tiles::corruption_system),
+        )
         .add_systems(Update, player::despawn_temporary_sprites)
         .add_systems(Update, player::sync_player_camera)
-        .add_systems(Update, collectables::collect_notes)
+        .add_systems(
+            Update,
+            player::pulse_player_on_beat.after(audio::tick_beat_clock),
+        )
+        .add_systems(
+            Update,
+            (
+                collectables::collect_notes,
+                collectables::emit_note_sounds.after(audio::tick_beat_clock),
+            )
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                music::update_music_mood
+                    .after(audio::tick_beat_clock)
+                    .run_if(resource_exists::<TileRegistry>),
+                music::mix_music_layers.after(music::update_music_mood),
+            ),
+        )
+        .add_systems(
+            Update,
+            (
+                audio::mixer_controls,
+                audio::save_mixer_settings.after(audio::mixer_controls),
+                audio::apply_channel_volumes
+                    .after(audio::mixer_controls)
+                    .after(player::play_notes)
+                    .after(rhythm::play_judged_notes)
+                    .after(collectables::emit_note_sounds),
+            ),
+        )
         .run();
 }
@@ -0,0 +1,235 @@
+// music.rs
+use crate::audio::{
+    audio_source, low_pass, AmbienceChannel, BeatEvent, Channel, CurrentBPM, Ducking,
+    MixerSettings, MusicChannel, Note, SynthVoice, DUCKING_SECONDS, SAMPLE_RATE,
+};
+use crate::player::Player;
+use crate::tiles::{TileMap, TileRegistry, TileType};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy_kira_audio::{
+    AudioChannel, AudioControl, AudioEasing, AudioInstance, AudioSource, AudioTween,
+};
+
+/// Fraction of corrupted tiles at which the music is as dissonant as it gets.
+const FULL_CORRUPTION: f32 = 0.5;
+/// Length of the synthesized stems, which loop.
+const STEM_SECONDS: f32 = 8.0;
+
+/// What the adaptive music responds to, each from 0 to 1.
+#[derive(Resource, Clone, Copy, Default, PartialEq, Debug)]
+pub struct MusicMood {
+    /// How much of the stage has been corrupted, scaled by `FULL_CORRUPTION`.
+    pub corruption: f32,
+    /// How many of the song's notes the player has collected.
+    pub restoration: f32,
+}
+
+/// One looping layer of the background music.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stem {
+    /// The D soundscape, which sags flat as corruption spreads.
+    Soundscape,
+    /// A low open fifth on D that grows as notes are collected.
+    Drone,
+    /// A high D major chord for a mostly restored hymn.
+    Choir,
+    /// The choir through a low-pass filter. Crossfading to it muffles the choir under corruption.
+    MuffledChoir,
+    /// A detuned tritone that creeps in with the corruption.
+    Dissonance,
+}
+
+impl Stem {
+    pub const ALL: [Stem; 5] = [
+        Stem::Soundscape,
+        Stem::Drone,
+        Stem::Choir,
+        Stem::MuffledChoir,
+        Stem::Dissonance,
+    ];
+
+    /// The soundscape is ambience, everything else is music.
+    pub fn channel(self) -> Channel {
+        match self {
+            Stem::Soundscape => Channel::Ambience,
+            _ => Channel::Music,
+        }
+    }
+
+    fn source(
+        self,
+        asset_server: &AssetServer,
+        audio_sources: &mut Assets<AudioSource>,
+    ) -> Handle<AudioSource> {
+        let mut render = |voice: SynthVoice, pitches: &[usize], cutoff: Option<f32>| {
+            let notes: Vec<Note> = pitches.iter().copied().map(Note).collect();
+            let mut samples = voice.render_loop(&notes, STEM_SECONDS, SAMPLE_RATE);
+            if let Some(cutoff) = cutoff {
+                low_pass(&mut samples, cutoff, SAMPLE_RATE);
+            }
+            audio_sources.add(audio_source(samples))
+        };
+
+        match self {
+            Stem::Soundscape => asset_server.load("D-soundscape.wav"),
+            Stem::Drone => render(SynthVoice::organ(), &[38, 45], None),
+            Stem::Choir => render(SynthVoice::organ(), &[62, 66, 69], None),
+            Stem::MuffledChoir => render(SynthVoice::organ(), &[62, 66, 69], Some(400.0)),
+            Stem::Dissonance => render(SynthVoice::chiptune(), &[51, 57], Some(1200.0)),
+        }
+    }
+
+    /// Volume and playback rate of the stem for `mood`.
+    pub fn mix(self, mood: &MusicMood) -> (f64, f64) {
+        let corruption = mood.corruption as f64;
+        let restoration = mood.restoration as f64;
+        // The choir only joins for the second half of the hymn
+        let choir = ((restoration - 0.5) * 2.0).clamp(0.0, 1.0);
+
+        match self {
+            Stem::Soundscape => (0.15 * (1.0 - 0.5 * corruption), 1.0 - 0.04 * corruption),
+            Stem::Drone => (0.12 * restoration, 1.0),
+            Stem::Choir => (0.1 * choir * (1.0 - corruption), 1.0),
+            Stem::MuffledChoir => (0.1 * choir * corruption, 1.0 - 0.02 * corruption),
+            Stem::Dissonance => (
+                0.08 * corruption * (1.0 - 0.5 * restoration),
+                1.0 + 0.015 * corruption,
+            ),
+        }
+    }
+}
+
+/// The playing instance of every stem.
+#[derive(Resource)]
+pub struct MusicLayers {
+    pub instances: Vec<(Stem, Handle<AudioInstance>)>,
+}
+
+pub fn setup_music_layers(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    ambience_channel: Res<AudioChannel<AmbienceChannel>>,
+    mixer: Res<MixerSettings>,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+) {
+    let mood = MusicMood::default();
+    let instances = Stem::ALL
+        .into_iter()
+        .map(|stem| {
+            let (volume, playback_rate) = stem.mix(&mood);
+            let source = stem.source(&asset_server, &mut audio_sources);
+            let mut play = match stem.channel() {
+                Channel::Ambience => ambience_channel.play(source),
+                _ => music_channel.play(source),
+            };
+            play.looped()
+                .with_volume(volume * mixer.volume(stem.channel()))
+                .with_playback_rate(playback_rate);
+
+            if stem == Stem::Soundscape {
+                play.loop_from(0.5)
+                    .fade_in(AudioTween::new(
+                        Duration::from_secs(2),
+                        AudioEasing::OutPowi(2),
+                    ))
+                    .reverse();
+            }
+
+            (stem, play.handle())
+        })
+        .collect();
+
+    commands.insert_resource(MusicLayers { instances });
+}
+
+/// Measures the corruption and restoration once a beat.
+pub fn update_music_mood(
+    mut beat_events: EventReader<BeatEvent>,
+    tile_map: Res<TileMap>,
+    tile_query: Query<&TileType>,
+    registry: Res<TileRegistry>,
+    player: Res<Player>,
+    mut mood: ResMut<MusicMood>,
+) {
+    if beat_events.read().last().is_none() || tile_map.tiles.is_empty() {
+        return;
+    }
+
+    let corrupted = tile_map
+        .tiles
+        .values()
+        .filter(|&&entity| {
+            tile_query
+                .get(entity)
+                .is_ok_and(|tile_type| registry.is_corrupted(*tile_type))
+        })
+        .count();
+    let corrupted_fraction = corrupted as f32 / tile_map.tiles.len() as f32;
+
+    let song_notes = player.current_song.distinct_notes();
+    let collected = song_notes
+        .iter()
+        .filter(|note| player.current_notes.contains(note))
+        .count();
+
+    mood.set_if_neq(MusicMood {
+        corruption: (corrupted_fraction / FULL_CORRUPTION).min(1.0),
+        restoration: collected as f32 / song_notes.len().max(1) as f32,
+    });
+}
+
+/// Whether notes are ducking the music, and whether they were last frame.
+#[derive(SystemParam)]
+pub struct DuckingState<'w, 's> {
+    ducking: Res<'w, Ducking>,
+    time: Res<'w, Time>,
+    was_ducking: Local<'s, bool>,
+}
+
+impl DuckingState<'_, '_> {
+    /// Returns whether the music is ducked now, and whether that changed since
+    /// the last call.
+    pub fn update(&mut self) -> (bool, bool) {
+        let is_ducking = self.ducking.is_ducking(self.time.elapsed_seconds());
+        let changed = is_ducking != *self.was_ducking;
+        *self.was_ducking = is_ducking;
+        (is_ducking, changed)
+    }
+}
+
+/// Glides every stem towards its mix for the current mood over a beat, and
+/// ducks them quickly while notes play.
+pub fn mix_music_layers(
+    mood: Res<MusicMood>,
+    layers: Res<MusicLayers>,
+    mixer: Res<MixerSettings>,
+    mut ducking: DuckingState,
+    current_bpm: Res<CurrentBPM>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+) {
+    let (is_ducking, duck_changed) = ducking.update();
+
+    if !mood.is_changed() && !mixer.is_changed() && !duck_changed {
+        return;
+    }
+
+    let seconds = if duck_changed {
+        DUCKING_SECONDS
+    } else {
+        current_bpm.seconds(1.0)
+    };
+    let tween = AudioTween::linear(Duration::from_secs_f32(seconds));
+
+    for (stem, handle) in &layers.instances {
+        if let Some(instance) = audio_instances.get_mut(handle) {
+            let (volume, playback_rate) = stem.mix(&mood);
+            let volume = volume * mixer.ducked_volume(stem.channel(), is_ducking);
+            instance.set_volume(volume, tween.clone());
+            instance.set_playback_rate(playback_rate, tween.clone());
+        }
+    }
+}
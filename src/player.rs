@@ -1,89 +1,79 @@
 // player.rs
 
 use crate::{
-    audio::{CurrentBPM, Note, NoteAudioHandles, Song, EIGHTH_NOTE_DURATION},
-    Tile, TileMap, TileType, STAGE_SIZE, TILE_SIZE,
+    audio::{
+        BeatClock, BeatEvent, Channel, CurrentBPM, CurrentSong, Ducking, MixerSettings, Note,
+        NoteSounds, NotesChannel, Song, SongChanged, NOTE_VOLUME,
+    },
+    game_state::SongPlayedThrough,
+    Purification, TileMap, TileProperties, PULSE_RADIUS_PER_SECOND, SPAWN_TILE, TILE_SIZE,
 };
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::utils::Duration;
-use bevy_kira_audio::{Audio, AudioControl, AudioEasing, AudioPlugin, AudioSource, AudioTween};
+use bevy_kira_audio::{AudioChannel, AudioControl};
 use bevy_rapier2d::prelude::*;
 
-#[derive(Resource)]
-pub struct CorruptedTileTexture(pub Handle<Image>);
-
 #[derive(Resource, Component, Clone)]
 pub struct Player {
     pub current_notes: Vec<Note>,
     pub current_song: Song,
     pub note_index: usize,
-    pub timer: MyTimer,
+    /// `BeatClock` position the next step is due at while the player walks.
+    pub next_step: Option<f64>,
+}
+
+impl Player {
+    /// The harmony voice joins in once every note it uses has been collected.
+    pub fn harmony_unlocked(&self) -> bool {
+        let harmony = &self.current_song.harmony;
+        !harmony.is_empty()
+            && harmony
+                .iter()
+                .flat_map(|step| &step.notes)
+                .all(|note| self.current_notes.contains(note))
+    }
+
+    /// The collected notes of the melody step at `index`, along with any
+    /// harmony starting during that step once the harmony is unlocked.
+    pub fn playable_notes(&self, index: usize) -> Vec<Note> {
+        let song = &self.current_song;
+        let Some(step) = song.notes.get(index) else {
+            return vec![];
+        };
+
+        let mut notes: Vec<Note> = step
+            .notes
+            .iter()
+            .copied()
+            .filter(|note| self.current_notes.contains(note))
+            .collect();
+
+        if self.harmony_unlocked() {
+            let start = song.beat_at(index);
+            for &note in song
+                .harmony_between(start, start + step.beats)
+                .flat_map(|step| &step.notes)
+            {
+                if !notes.contains(&note) {
+                    notes.push(note);
+                }
+            }
+        }
+
+        notes
+    }
 }
 
 pub fn setup_player(mut commands: Commands, asset_server: Res<AssetServer>) {
     let player = Player {
         current_notes: vec![],
-        current_song: Song::new(&[
-            (Some(2), 1.0),  // Eighth note
-            (Some(6), 1.0),  // Eighth note
-            (Some(9), 3.0),  // Half note (sustained)
-            (Some(11), 1.0), // Quarter note (sustained)
-            (Some(9), 1.0),  // Half note (sustained)
-            (Some(6), 1.0),
-            (Some(2), 4.0),
-            (Some(2), 2.0),
-            (Some(4), 3.0),
-            (Some(4), 1.0),
-            (Some(2), 1.0),
-            (Some(4), 1.0),
-            (Some(6), 4.0),
-            (Some(2), 1.0),
-            (Some(6), 1.0),
-            (Some(9), 3.0),
-            (Some(11), 1.0),
-            (Some(9), 1.0),
-            (Some(6), 1.0),
-            (Some(2), 4.0),
-            (Some(2), 1.0),
-            (Some(4), 1.0),
-            (Some(6), 3.0),
-            (Some(7), 1.0),
-            (Some(6), 1.0),
-            (Some(4), 1.0),
-            (Some(2), 6.0),
-            (Some(9), 3.0),
-            (Some(11), 1.0),
-            (Some(9), 1.0),
-            (Some(6), 1.0),
-            (Some(14), 6.0),
-            (Some(9), 3.0),
-            (Some(11), 1.0),
-            (Some(9), 1.0),
-            (Some(6), 1.0),
-            (Some(4), 6.0),
-            (Some(9), 3.0),
-            (Some(11), 1.0),
-            (Some(9), 1.0),
-            (Some(6), 1.0),
-            (Some(14), 1.0),
-            (Some(13), 1.0),
-            (Some(11), 2.0),
-            (Some(14), 2.0),
-            (Some(6), 3.0),
-            (Some(7), 1.0),
-            (Some(6), 1.0),
-            (Some(4), 1.0),
-            (Some(2), 4.0),
-        ]),
-        timer: MyTimer {
-            timer: Timer::from_seconds(EIGHTH_NOTE_DURATION, TimerMode::Repeating),
-            duration: EIGHTH_NOTE_DURATION,
-        },
+        current_song: Song::default(),
         note_index: 0,
+        next_step: None,
     };
 
-    commands.insert_resource(CorruptedTileTexture(
-        asset_server.load("corrupted_tile_1.png"),
+    commands.insert_resource(CurrentSong(
+        asset_server.load("songs/the_last_hymn.song.ron"),
     ));
 
     // Camera
@@ -100,10 +90,8 @@ pub fn setup_player(mut commands: Commands, asset_server: Res<AssetServer>) {
                 custom_size: Some(Vec2::new(8.0, 8.0)),
                 ..default()
             },
-            transform: Transform::from_xyz(
-                (STAGE_SIZE.0 / 2) as f32 * TILE_SIZE,
-                (STAGE_SIZE.1 / 2) as f32 * TILE_SIZE,
-                100.0,
+            transform: Transform::from_translation(
+                (SPAWN_TILE.as_vec2() * TILE_SIZE).extend(100.0),
             ),
             ..default()
         },
@@ -115,57 +103,201 @@ pub fn setup_player(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 }
 
+pub fn apply_current_song(
+    current_song: Res<CurrentSong>,
+    songs: Res<Assets<Song>>,
+    mut song_events: EventReader<AssetEvent<Song>>,
+    mut song_changed: EventWriter<SongChanged>,
+    mut player: ResMut<Player>,
+    mut current_bpm: ResMut<CurrentBPM>,
+    mut beat_clock: ResMut<BeatClock>,
+) {
+    let reloaded = song_events.read().fold(false, |reloaded, event| {
+        reloaded
+            || event.is_loaded_with_dependencies(&current_song.0)
+            || event.is_modified(&current_song.0)
+    });
+
+    if !reloaded && !current_song.is_changed() {
+        return;
+    }
+
+    let Some(song) = songs.get(&current_song.0) else {
+        return;
+    };
+
+    info!("Now playing {:?}", song.title);
+    *current_bpm = CurrentBPM::new(song.bpm);
+    beat_clock.reset();
+    player.current_song = fit_to_samples(song);
+    player.note_index = 0;
+    player.next_step = None;
+    song_changed.send(SongChanged);
+}
+
+/// Moves `song` into the range of the note samples if it needs to, so every
+/// note can be played whichever instrument is picked.
+fn fit_to_samples(song: &Song) -> Song {
+    let (low, high) = Note::sample_range();
+    match song.fit_to_range(low, high) {
+        Some(0) => song.clone(),
+        Some(semitones) => {
+            info!("Transposing {:?} by {} semitones", song.title, semitones);
+            song.transposed(semitones).unwrap_or_else(|| song.clone())
+        }
+        None => {
+            warn!("{:?} spans more notes than there are samples", song.title);
+            song.clone()
+        }
+    }
+}
+
+/// What playing the song sends out, and what it keeps track of to know when.
+#[derive(SystemParam)]
+pub struct SongProgress<'w, 's> {
+    purification: EventWriter<'w, Purification>,
+    song_played: EventWriter<'w, SongPlayedThrough>,
+    /// Whether a melody note of the current phrase couldn't be played.
+    phrase_broken: Local<'s, bool>,
+    /// Whether a melody note of this pass through the song couldn't be played.
+    song_broken: Local<'s, bool>,
+}
+
+/// Plays notes on the notes channel, ducking the music under them.
+#[derive(SystemParam)]
+pub struct NotePlayer<'w> {
+    notes_channel: Res<'w, AudioChannel<NotesChannel>>,
+    mixer: Res<'w, MixerSettings>,
+    ducking: ResMut<'w, Ducking>,
+    time: Res<'w, Time>,
+    note_sounds: NoteSounds<'w>,
+}
+
+impl NotePlayer<'_> {
+    /// Plays `notes` together as a step lasting `duration` seconds.
+    pub fn play(&mut self, notes: &[Note], duration: f32) {
+        for &note in notes {
+            if let Some(note_handle) = self.note_sounds.handle(note) {
+                self.notes_channel
+                    .play(note_handle)
+                    .with_volume(NOTE_VOLUME * self.mixer.volume(Channel::Notes));
+            }
+        }
+        self.ducking.duck(self.time.elapsed_seconds(), duration);
+    }
+}
+
+/// Shows each played step around the player.
+#[derive(SystemParam)]
+pub struct StepPulse<'w, 's> {
+    commands: Commands<'w, 's>,
+    asset_server: Res<'w, AssetServer>,
+    player_query: Query<'w, 's, &'static Transform, With<Player>>,
+}
+
+impl StepPulse<'_, '_> {
+    /// Spawns a sprite on the player for a step lasting `duration` seconds and
+    /// returns the pulse of purification the step sends out, if there is a player.
+    pub fn pulse(&mut self, duration: f32) -> Option<Purification> {
+        let player_transform = self.player_query.get_single().ok()?;
+        spawn_temporary_sprite(
+            &mut self.commands,
+            &self.asset_server,
+            player_transform,
+            duration,
+        );
+        Some(Purification::Pulse {
+            center: player_transform.translation.truncate(),
+            radius: duration * PULSE_RADIUS_PER_SECOND,
+        })
+    }
+}
+
 pub fn play_notes(
     keyboard: Res<ButtonInput<KeyCode>>,
-    audio: Res<Audio>,
-    note_handles: Res<NoteAudioHandles>,
+    mut note_player: NotePlayer,
+    mut step_pulse: StepPulse,
     mut player: ResMut<Player>,
-    time: Res<Time>,
-    commands: Commands,
-    player_query: Query<&Transform, With<Player>>,
-    asset_server: Res<AssetServer>,
+    beat_clock: Res<BeatClock>,
+    mut current_bpm: ResMut<CurrentBPM>,
+    mut progress: SongProgress,
 ) {
-    if keyboard.pressed(KeyCode::KeyA)
+    let walking = keyboard.pressed(KeyCode::KeyA)
         || keyboard.pressed(KeyCode::KeyS)
         || keyboard.pressed(KeyCode::KeyW)
-        || keyboard.pressed(KeyCode::KeyD)
-    {
-        player.timer.timer.tick(time.delta());
-
-        if player.timer.timer.finished() {
-            while let Some(&(note, duration)) = player.current_song.0.get(player.note_index) {
-                if let Some(note) = note {
-                    if player.current_notes.contains(&note) {
-                        let note_handle = note_handles.handles[note.0].clone();
-                        audio.play(note_handle).with_volume(5.0);
-
-                        if let Ok(player_transform) = player_query.get_single() {
-                            spawn_temporary_sprite(
-                                commands,
-                                asset_server,
-                                player_transform,
-                                duration,
-                            );
-                        }
-
-                        player.timer.duration = duration;
-                        player
-                            .timer
-                            .timer
-                            .set_duration(Duration::from_secs_f32(duration));
-                        player.note_index += 1;
-                        break;
-                    }
-                }
-                player.note_index += 1;
+        || keyboard.pressed(KeyCode::KeyD);
+    if !walking {
+        player.next_step = None;
+        return;
+    }
+
+    // Steps land on the clock's eighth notes, starting from the next one when
+    // the player sets off or is back after more than an eighth note away
+    let position = beat_clock.position;
+    let due = match player.next_step {
+        Some(due) if position - due <= 0.5 => due,
+        _ => (position * 2.0).ceil() / 2.0,
+    };
+    if position < due {
+        player.next_step = Some(due);
+        return;
+    }
+    // Without a collected note to play, try again on the next eighth note
+    player.next_step = Some(due + 0.5);
+
+    while let Some(step) = player.current_song.notes.get(player.note_index) {
+        // Each pass through the song starts over at its first step
+        if player.note_index == 0 {
+            *progress.song_broken = false;
+        }
+        let beats = step.beats;
+        let notes = player.playable_notes(player.note_index);
+        let ends_phrase = player.current_song.ends_phrase(player.note_index);
+
+        // A melody note that hasn't been collected breaks the phrase
+        if !step.notes.iter().all(|note| notes.contains(note)) {
+            *progress.phrase_broken = true;
+            *progress.song_broken = true;
+        }
+        if ends_phrase && notes.is_empty() && !step.is_rest() {
+            progress.purification.send(Purification::PhraseBroken);
+            *progress.phrase_broken = false;
+        }
+
+        if !notes.is_empty() {
+            // Follow the song's tempo markers, which the BeatClock picks up
+            let beat = player.current_song.beat_at(player.note_index);
+            let bpm = player.current_song.bpm_at(beat);
+            if bpm != current_bpm.bpm {
+                *current_bpm = CurrentBPM::new(bpm);
             }
+            let duration = current_bpm.seconds(beats);
 
-            if player.note_index >= player.current_song.0.len() {
-                player.note_index = 0;
+            note_player.play(&notes, duration);
+            if let Some(pulse) = step_pulse.pulse(duration) {
+                progress.purification.send(pulse);
+            }
+            if ends_phrase {
+                progress.purification.send(if *progress.phrase_broken {
+                    Purification::PhraseBroken
+                } else {
+                    Purification::PhraseCompleted
+                });
+                *progress.phrase_broken = false;
             }
+
+            player.next_step = Some(due + beats as f64);
+            player.note_index += 1;
+            break;
         }
-    } else {
-        player.timer.timer.reset();
+        player.note_index += 1;
+    }
+
+    if player.note_index >= player.current_song.notes.len() {
+        if !*progress.song_broken && !player.current_song.notes.is_empty() {
+            progress.song_played.send(SongPlayedThrough);
+        }
+        player.note_index = 0;
     }
 }
 
@@ -174,7 +306,7 @@ pub fn player_movement(
     mut player_query: Query<&mut Transform, With<Player>>,
     time: Res<Time>,
     tile_map: Res<TileMap>,
-    tile_query: Query<(&mut TileType)>,
+    tile_query: Query<&TileProperties>,
     current_bpm: Res<CurrentBPM>,
 ) {
     if let Ok(mut transform) = player_query.get_single_mut() {
@@ -197,27 +329,23 @@ pub fn player_movement(
             direction = direction.normalize();
         }
 
-        let mut new_translation =
-            transform.translation + direction * current_bpm.bpm * time.delta_seconds();
-
-        // Clamp the player's position within the stage boundaries
-        new_translation.x = new_translation
-            .x
-            .clamp(0.0, (STAGE_SIZE.0 as f32 - 1.0) * TILE_SIZE);
-        new_translation.y = new_translation
-            .y
-            .clamp(0.0, (STAGE_SIZE.1 as f32 - 1.0) * TILE_SIZE);
-
-        // Check if the new position is on a corrupted tile
-        let new_tile_x = (new_translation.x / TILE_SIZE) as i32;
-        let new_tile_y = (new_translation.y / TILE_SIZE) as i32;
-
-        if let Some(tile_entity) = tile_map.tiles.get(&(new_tile_x, new_tile_y)) {
-            if let Ok(tile_type) = tile_query.get(*tile_entity) {
-                if !tile_type.is_corrupted_tile() {
-                    transform.translation = new_translation;
-                }
-            }
+        // The tile underfoot sets the pace
+        let tile_at = |translation: Vec3| {
+            let tile = (translation.truncate() / TILE_SIZE).round().as_ivec2();
+            tile_map
+                .tiles
+                .get(&(tile.x, tile.y))
+                .and_then(|entity| tile_query.get(*entity).ok())
+        };
+        let speed = tile_at(transform.translation).map_or(1.0, |properties| properties.speed);
+
+        let new_translation =
+            transform.translation + direction * current_bpm.bpm * speed * time.delta_seconds();
+
+        // Only move onto walkable tiles. Tiles that aren't loaded yet block
+        // movement too.
+        if tile_at(new_translation).is_some_and(|properties| properties.walkable) {
+            transform.translation = new_translation;
         }
     }
 }
@@ -236,6 +364,23 @@ pub fn sync_player_camera(
     camera_transform.translation = player_transform.translation;
 }
 
+/// Pulses the player sprite with the `BeatClock`, a little harder on the downbeat.
+pub fn pulse_player_on_beat(
+    beat_clock: Res<BeatClock>,
+    mut beat_events: EventReader<BeatEvent>,
+    mut strength: Local<f32>,
+    mut player_query: Query<&mut Sprite, With<Player>>,
+) {
+    if let Some(beat) = beat_events.read().last() {
+        *strength = if beat.beat_in_bar == 0 { 0.3 } else { 0.15 };
+    }
+
+    if let Ok(mut sprite) = player_query.get_single_mut() {
+        let pulse = (1.0 - beat_clock.phase()).powi(2) * *strength;
+        sprite.custom_size = Some(Vec2::splat(8.0 * (1.0 + pulse)));
+    }
+}
+
 #[derive(Component)]
 pub struct TemporarySprite;
 
@@ -245,13 +390,13 @@ pub struct MyTimer {
     pub duration: f32,
 }
 
-fn spawn_temporary_sprite(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
+pub fn spawn_temporary_sprite(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
     player_transform: &Transform,
     duration: f32,
 ) {
-    let transform = player_transform.clone();
+    let transform = *player_transform;
 
     commands.spawn((
         SpriteBundle {
@@ -275,6 +420,7 @@ pub fn despawn_temporary_sprites(
     mut commands: Commands,
     time: Res<Time>,
     player: Res<Player>,
+    current_bpm: Res<CurrentBPM>,
     mut sprite_query: Query<(Entity, &mut Sprite, &mut MyTimer), With<TemporarySprite>>,
 ) {
     for (entity, mut sprite, mut timer) in sprite_query.iter_mut() {
@@ -284,8 +430,8 @@ pub fn despawn_temporary_sprites(
         let current_note_duration = player
             .note_index
             .checked_sub(1)
-            .and_then(|index| player.current_song.0.get(index))
-            .map(|&(_, duration)| duration)
+            .and_then(|index| player.current_song.notes.get(index))
+            .map(|step| current_bpm.seconds(step.beats))
             .unwrap_or(0.0);
 
         // Calculate the remaining time ratio based on the current note duration
@@ -0,0 +1,344 @@
+// rhythm.rs
+use crate::audio::{BeatClock, CurrentBPM, Note, SongChanged};
+use crate::game_state::SongPlayedThrough;
+use crate::player::{NotePlayer, Player, StepPulse};
+use crate::tiles::Purification;
+
+use bevy::prelude::*;
+
+/// Offsets from a note's beat, in beats, that still count for each grade.
+pub const PERFECT_WINDOW: f64 = 0.1;
+pub const GOOD_WINDOW: f64 = 0.25;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Grade {
+    Perfect,
+    Good,
+    Miss,
+}
+
+impl Grade {
+    /// Grades an input `offset` beats away from its target.
+    pub fn from_offset(offset: f64) -> Grade {
+        match offset.abs() {
+            offset if offset <= PERFECT_WINDOW => Grade::Perfect,
+            offset if offset <= GOOD_WINDOW => Grade::Good,
+            _ => Grade::Miss,
+        }
+    }
+
+    /// Points for the grade before the combo bonus.
+    pub fn points(self) -> u32 {
+        match self {
+            Grade::Perfect => 100,
+            Grade::Good => 50,
+            Grade::Miss => 0,
+        }
+    }
+}
+
+/// In rhythm mode each collected note of the song has to be played with Space
+/// on its beat, instead of playing itself while the player walks.
+#[derive(Resource, Default)]
+pub struct RhythmMode {
+    pub enabled: bool,
+    /// `BeatClock` position that lines up with the first beat of the song.
+    pub song_start: f64,
+    /// Index of the next note in the song waiting to be judged.
+    pub next_note: usize,
+    /// Whether every step of this pass through the song has been hit so far.
+    pub clean_pass: bool,
+    /// Whether a step of the current phrase has been missed.
+    pub phrase_broken: bool,
+}
+
+impl RhythmMode {
+    /// Lines the song up with the downbeat after the current bar.
+    pub fn count_in(&mut self, beat_clock: &BeatClock) {
+        self.song_start = ((beat_clock.bar() + 1) * beat_clock.beats_per_bar as u64) as f64;
+        self.next_note = 0;
+        self.clean_pass = true;
+        self.phrase_broken = false;
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct RhythmScore {
+    pub score: u32,
+    pub combo: u32,
+    pub best_combo: u32,
+}
+
+impl RhythmScore {
+    /// Counts a judged step towards the combo and adds its points. Every 10
+    /// notes of combo adds another 10% to each hit; a miss breaks the combo.
+    pub fn add(&mut self, grade: Grade) {
+        if grade == Grade::Miss {
+            self.combo = 0;
+        } else {
+            self.combo += 1;
+            self.best_combo = self.best_combo.max(self.combo);
+        }
+        self.score += grade.points() * (10 + self.combo / 10) / 10;
+    }
+}
+
+/// Sent for every step of the song the player hits or misses.
+#[derive(Event, Clone, Debug)]
+pub struct NoteJudged {
+    /// The step's collected notes, with any unlocked harmony.
+    pub notes: Vec<Note>,
+    pub grade: Grade,
+    /// Whether the step closes a phrase of the song.
+    pub ends_phrase: bool,
+}
+
+#[derive(Component)]
+pub struct RhythmHud;
+
+pub fn rhythm_mode_enabled(rhythm_mode: Res<RhythmMode>) -> bool {
+    rhythm_mode.enabled
+}
+
+pub fn toggle_rhythm_mode(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    beat_clock: Res<BeatClock>,
+    mut song_changed: EventReader<SongChanged>,
+    mut rhythm_mode: ResMut<RhythmMode>,
+) {
+    let toggled = keyboard.just_pressed(KeyCode::KeyR);
+    if toggled {
+        rhythm_mode.enabled = !rhythm_mode.enabled;
+        info!("Rhythm mode: {}", rhythm_mode.enabled);
+    }
+
+    if rhythm_mode.enabled && (toggled || song_changed.read().last().is_some()) {
+        rhythm_mode.count_in(&beat_clock);
+    }
+}
+
+/// The `BeatClock` keeps going outside `Playing`, so the song is counted in
+/// again on the way back rather than missing every step that went by.
+pub fn count_in_on_play(beat_clock: Res<BeatClock>, mut rhythm_mode: ResMut<RhythmMode>) {
+    if rhythm_mode.enabled {
+        rhythm_mode.count_in(&beat_clock);
+    }
+}
+
+pub fn judge_notes(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    beat_clock: Res<BeatClock>,
+    mut rhythm_mode: ResMut<RhythmMode>,
+    mut player: ResMut<Player>,
+    mut note_judged: EventWriter<NoteJudged>,
+    mut song_played: EventWriter<SongPlayedThrough>,
+) {
+    let song = &player.current_song;
+    let playable = |index: usize| !player.playable_notes(index).is_empty();
+    if !(0..song.notes.len()).any(playable) {
+        return;
+    }
+
+    let mut pressed = keyboard.just_pressed(KeyCode::Space);
+    let mut last_hit = None;
+
+    loop {
+        // Skip rests and steps the player hasn't collected any notes of yet
+        while rhythm_mode.next_note < song.notes.len() && !playable(rhythm_mode.next_note) {
+            rhythm_mode.next_note += 1;
+        }
+        if rhythm_mode.next_note >= song.notes.len() {
+            // Loop the song straight after its last beat
+            if rhythm_mode.clean_pass {
+                song_played.send(SongPlayedThrough);
+            }
+            rhythm_mode.song_start += song.beat_at(song.notes.len()) as f64;
+            rhythm_mode.next_note = 0;
+            rhythm_mode.clean_pass = true;
+            continue;
+        }
+
+        let target = rhythm_mode.song_start + song.beat_at(rhythm_mode.next_note) as f64;
+        let offset = beat_clock.position - target;
+
+        if pressed && offset.abs() <= GOOD_WINDOW {
+            pressed = false;
+            note_judged.send(NoteJudged {
+                notes: player.playable_notes(rhythm_mode.next_note),
+                grade: Grade::from_offset(offset),
+                ends_phrase: song.ends_phrase(rhythm_mode.next_note),
+            });
+            last_hit = Some(rhythm_mode.next_note);
+        } else if offset > GOOD_WINDOW {
+            note_judged.send(NoteJudged {
+                notes: player.playable_notes(rhythm_mode.next_note),
+                grade: Grade::Miss,
+                ends_phrase: song.ends_phrase(rhythm_mode.next_note),
+            });
+            rhythm_mode.clean_pass = false;
+        } else {
+            break;
+        }
+        rhythm_mode.next_note += 1;
+    }
+
+    // Pressing between notes breaks the combo too
+    if pressed {
+        note_judged.send(NoteJudged {
+            notes: player.playable_notes(rhythm_mode.next_note),
+            grade: Grade::Miss,
+            ends_phrase: false,
+        });
+        rhythm_mode.clean_pass = false;
+    }
+
+    if let Some(index) = last_hit {
+        player.note_index = index + 1;
+    }
+}
+
+/// Plays the steps the player hits and purifies like `play_notes` does: each
+/// hit sends a pulse, and a miss breaks the phrase it falls in.
+pub fn play_judged_notes(
+    mut note_judged: EventReader<NoteJudged>,
+    mut rhythm_mode: ResMut<RhythmMode>,
+    mut purification: EventWriter<Purification>,
+    mut note_player: NotePlayer,
+    mut step_pulse: StepPulse,
+    player: Res<Player>,
+    current_bpm: Res<CurrentBPM>,
+) {
+    for judged in note_judged.read() {
+        if judged.grade == Grade::Miss {
+            purification.send(Purification::PhraseBroken);
+            rhythm_mode.phrase_broken = !judged.ends_phrase;
+            continue;
+        }
+
+        let duration = player
+            .note_index
+            .checked_sub(1)
+            .and_then(|index| player.current_song.notes.get(index))
+            .map(|step| current_bpm.seconds(step.beats))
+            .unwrap_or(0.0);
+        note_player.play(&judged.notes, duration);
+        if let Some(pulse) = step_pulse.pulse(duration) {
+            purification.send(pulse);
+        }
+        if judged.ends_phrase {
+            purification.send(if rhythm_mode.phrase_broken {
+                Purification::PhraseBroken
+            } else {
+                Purification::PhraseCompleted
+            });
+            rhythm_mode.phrase_broken = false;
+        }
+    }
+}
+
+pub fn score_notes(
+    mut note_judged: EventReader<NoteJudged>,
+    mut rhythm_score: ResMut<RhythmScore>,
+) {
+    for judged in note_judged.read() {
+        rhythm_score.add(judged.grade);
+        info!(
+            "{:?} {:?} (combo {})",
+            judged.grade, judged.notes, rhythm_score.combo
+        );
+    }
+}
+
+pub fn spawn_rhythm_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Px(8.0),
+            ..default()
+        }),
+        RhythmHud,
+    ));
+}
+
+pub fn update_rhythm_hud(
+    rhythm_mode: Res<RhythmMode>,
+    rhythm_score: Res<RhythmScore>,
+    mut hud_query: Query<&mut Text, With<RhythmHud>>,
+) {
+    let Ok(mut text) = hud_query.get_single_mut() else {
+        return;
+    };
+
+    text.sections[0].value = if rhythm_mode.enabled {
+        format!(
+            "Score {}  Combo {}  Best {}",
+            rhythm_score.score, rhythm_score.combo, rhythm_score.best_combo
+        )
+    } else {
+        String::new()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grades_offsets_up_to_the_window_edges() {
+        for offset in [0.0, 0.1, -0.1] {
+            assert_eq!(Grade::from_offset(offset), Grade::Perfect, "{offset}");
+        }
+        for offset in [0.101, -0.101, 0.25, -0.25] {
+            assert_eq!(Grade::from_offset(offset), Grade::Good, "{offset}");
+        }
+        for offset in [0.251, -0.251, 2.0] {
+            assert_eq!(Grade::from_offset(offset), Grade::Miss, "{offset}");
+        }
+    }
+
+    #[test]
+    fn hits_build_a_combo_and_misses_break_it() {
+        let mut score = RhythmScore::default();
+        score.add(Grade::Perfect);
+        score.add(Grade::Good);
+        assert_eq!((score.score, score.combo, score.best_combo), (150, 2, 2));
+
+        score.add(Grade::Miss);
+        assert_eq!((score.score, score.combo, score.best_combo), (150, 0, 2));
+
+        score.add(Grade::Good);
+        assert_eq!((score.score, score.combo, score.best_combo), (200, 1, 2));
+    }
+
+    #[test]
+    fn every_ten_of_combo_adds_ten_percent() {
+        let mut score = RhythmScore::default();
+        for _ in 0..9 {
+            score.add(Grade::Perfect);
+        }
+        assert_eq!(score.score, 900);
+
+        // The 10th and 19th hits earn 110, the 20th 120
+        score.add(Grade::Perfect);
+        assert_eq!(score.score, 1010);
+        for _ in 0..9 {
+            score.add(Grade::Perfect);
+        }
+        assert_eq!(score.score, 2000);
+        score.add(Grade::Perfect);
+        assert_eq!(score.score, 2120);
+
+        score.add(Grade::Good);
+        assert_eq!(score.score, 2180);
+        assert_eq!((score.combo, score.best_combo), (21, 21));
+    }
+}
@@ -0,0 +1,77 @@
+// biome.rs
+use bevy::prelude::*;
+use std::ops::Range;
+
+use super::{TileRegistry, TileType};
+
+/// A kind of terrain: the tiles it is made of and the elevation and moisture
+/// noise values it covers.
+#[derive(Clone)]
+pub struct Biome {
+    pub elevation: Range<f32>,
+    pub moisture: Range<f32>,
+    /// Ids of the tiles it is made of, mixed by their spawn weights.
+    pub tiles: Vec<String>,
+}
+
+/// Biomes checked in order; the first one whose ranges contain a tile's noise
+/// values decides its type.
+#[derive(Resource, Clone)]
+pub struct BiomeTable {
+    pub biomes: Vec<Biome>,
+    /// Tiles used where no biome matches.
+    pub fallback: Vec<String>,
+    /// Noise features per tile. Smaller values give larger biomes.
+    pub frequency: f32,
+    pub octaves: u32,
+}
+
+impl Default for BiomeTable {
+    fn default() -> Self {
+        BiomeTable {
+            biomes: vec![
+                Biome {
+                    elevation: 0.0..0.36,
+                    moisture: 0.0..1.0,
+                    tiles: vec!["sand".to_string()],
+                },
+                Biome {
+                    elevation: 0.36..1.0,
+                    moisture: 0.6..1.0,
+                    tiles: vec!["flower".to_string()],
+                },
+                Biome {
+                    elevation: 0.56..1.0,
+                    moisture: 0.0..0.6,
+                    tiles: vec!["grass".to_string()],
+                },
+            ],
+            fallback: vec!["green".to_string()],
+            frequency: 1.0 / 16.0,
+            octaves: 3,
+        }
+    }
+}
+
+impl BiomeTable {
+    pub fn biome_at(&self, elevation: f32, moisture: f32) -> Option<&Biome> {
+        self.biomes.iter().find(|biome| {
+            biome.elevation.contains(&elevation) && biome.moisture.contains(&moisture)
+        })
+    }
+
+    /// The tile at the given noise values. `variety` is a number from 0 to 1
+    /// that picks between the tiles of the biome.
+    pub fn tile_at(
+        &self,
+        elevation: f32,
+        moisture: f32,
+        variety: f32,
+        registry: &TileRegistry,
+    ) -> TileType {
+        self.biome_at(elevation, moisture)
+            .and_then(|biome| registry.pick(&biome.tiles, variety))
+            .or_else(|| registry.pick(&self.fallback, variety))
+            .unwrap_or(TileType(0))
+    }
+}
@@ -0,0 +1,341 @@
+// corruption_rules.rs
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rand::Rng;
+
+use super::TileProperties;
+
+/// Which tiles count as neighbours of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// The four tiles sharing an edge.
+    VonNeumann,
+    /// The eight tiles sharing an edge or a corner.
+    Moore,
+}
+
+impl Neighbourhood {
+    pub fn offsets(self) -> &'static [(i32, i32)] {
+        match self {
+            Neighbourhood::VonNeumann => &[(0, 1), (0, -1), (1, 0), (-1, 0)],
+            Neighbourhood::Moore => &[
+                (0, 1),
+                (0, -1),
+                (1, 0),
+                (-1, 0),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ],
+        }
+    }
+}
+
+/// What the rules get to see of a healthy tile next to the corruption.
+pub struct TileContext<'a> {
+    pub position: (i32, i32),
+    /// Id of the tile's kind in the `TileRegistry`.
+    pub id: &'a str,
+    pub properties: TileProperties,
+    /// Whether the tile at a position is corrupted, before this tick's spread.
+    pub is_corrupted: &'a dyn Fn((i32, i32)) -> bool,
+}
+
+impl TileContext<'_> {
+    pub fn corrupted_neighbours(&self, neighbourhood: Neighbourhood) -> usize {
+        let (x, y) = self.position;
+        neighbourhood
+            .offsets()
+            .iter()
+            .filter(|(dx, dy)| (self.is_corrupted)((x + dx, y + dy)))
+            .count()
+    }
+}
+
+/// One part of how corruption spreads. A tile's chance of being corrupted in
+/// a tick is the product of every rule's factor.
+pub trait CorruptionRule: Send + Sync {
+    fn factor(&self, tile: &TileContext) -> f32;
+}
+
+/// Blocks spreading into tiles with fewer than `min` corrupted neighbours.
+pub struct NeighbourThreshold {
+    pub neighbourhood: Neighbourhood,
+    pub min: usize,
+}
+
+impl CorruptionRule for NeighbourThreshold {
+    fn factor(&self, tile: &TileContext) -> f32 {
+        if tile.corrupted_neighbours(self.neighbourhood) >= self.min {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Each corrupted neighbour independently has `chance` to spread into the tile.
+pub struct ProbabilisticGrowth {
+    pub neighbourhood: Neighbourhood,
+    pub chance: f32,
+}
+
+impl CorruptionRule for ProbabilisticGrowth {
+    fn factor(&self, tile: &TileContext) -> f32 {
+        let neighbours = tile.corrupted_neighbours(self.neighbourhood);
+        1.0 - (1.0 - self.chance).powi(neighbours as i32)
+    }
+}
+
+/// Scales the spread into each kind of tile, by tile id.
+pub struct SpreadRates {
+    pub rates: HashMap<String, f32>,
+    /// Used for tiles not listed in `rates`.
+    pub default: f32,
+}
+
+impl CorruptionRule for SpreadRates {
+    fn factor(&self, tile: &TileContext) -> f32 {
+        self.rates.get(tile.id).copied().unwrap_or(self.default)
+    }
+}
+
+/// Tiles hold out in proportion to their `corruption_resistance`.
+pub struct Resistance;
+
+impl CorruptionRule for Resistance {
+    fn factor(&self, tile: &TileContext) -> f32 {
+        1.0 - tile.properties.corruption_resistance
+    }
+}
+
+/// The rules `corruption_system` spreads corruption by.
+#[derive(Resource)]
+pub struct CorruptionRules {
+    pub rules: Vec<Box<dyn CorruptionRule>>,
+}
+
+impl Default for CorruptionRules {
+    fn default() -> Self {
+        CorruptionRules {
+            rules: vec![
+                // Spreads only across edges, but faster into tiles the
+                // corruption wraps around, which fills in its outline
+                Box::new(NeighbourThreshold {
+                    neighbourhood: Neighbourhood::VonNeumann,
+                    min: 1,
+                }),
+                Box::new(ProbabilisticGrowth {
+                    neighbourhood: Neighbourhood::Moore,
+                    chance: 0.06,
+                }),
+                Box::new(SpreadRates {
+                    // Dry sand lets it spread faster
+                    rates: HashMap::from([("sand".to_string(), 1.5)]),
+                    default: 1.0,
+                }),
+                Box::new(Resistance),
+            ],
+        }
+    }
+}
+
+impl CorruptionRules {
+    /// Chance from 0 to 1 that `tile` is corrupted this tick.
+    pub fn chance(&self, tile: &TileContext) -> f32 {
+        self.rules
+            .iter()
+            .map(|rule| rule.factor(tile))
+            .product::<f32>()
+            .clamp(0.0, 1.0)
+    }
+
+    /// One step of the automaton: the positions of `tiles` that become
+    /// corrupted. Every tile is judged on the corruption before the step, so
+    /// tiles corrupted in it don't spread until the next one. Works on plain
+    /// data, so spread patterns can be compared without running the game.
+    pub fn step<'a>(
+        &self,
+        tiles: impl IntoIterator<Item = TileContext<'a>>,
+        rng: &mut impl Rng,
+    ) -> Vec<(i32, i32)> {
+        tiles
+            .into_iter()
+            .filter(|tile| rng.gen::<f32>() < self.chance(tile))
+            .map(|tile| tile.position)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiles::{WorldSeed, CORRUPTION_STREAM};
+    use bevy::utils::HashSet;
+
+    const GRASS: TileProperties = TileProperties {
+        walkable: true,
+        speed: 1.0,
+        corruption_resistance: 0.0,
+    };
+
+    fn tile<'a>(
+        position: (i32, i32),
+        id: &'a str,
+        is_corrupted: &'a dyn Fn((i32, i32)) -> bool,
+    ) -> TileContext<'a> {
+        TileContext {
+            position,
+            id,
+            properties: GRASS,
+            is_corrupted,
+        }
+    }
+
+    #[test]
+    fn neighbour_threshold_counts_its_neighbourhood() {
+        // One corrupted tile across an edge of (0, 0) and one across a corner
+        let is_corrupted = |position| position == (0, 1) || position == (1, 1);
+        let origin = tile((0, 0), "grass", &is_corrupted);
+        let threshold = |neighbourhood, min| NeighbourThreshold { neighbourhood, min };
+
+        assert_eq!(threshold(Neighbourhood::VonNeumann, 1).factor(&origin), 1.0);
+        assert_eq!(threshold(Neighbourhood::VonNeumann, 2).factor(&origin), 0.0);
+        assert_eq!(threshold(Neighbourhood::Moore, 2).factor(&origin), 1.0);
+        assert_eq!(threshold(Neighbourhood::Moore, 3).factor(&origin), 0.0);
+
+        let is_corrupted = |position| position == (1, 1);
+        let corner_only = tile((0, 0), "grass", &is_corrupted);
+        assert_eq!(
+            threshold(Neighbourhood::VonNeumann, 1).factor(&corner_only),
+            0.0
+        );
+        assert_eq!(threshold(Neighbourhood::Moore, 1).factor(&corner_only), 1.0);
+    }
+
+    #[test]
+    fn probabilistic_growth_compounds_per_neighbour() {
+        let growth = ProbabilisticGrowth {
+            neighbourhood: Neighbourhood::Moore,
+            chance: 0.5,
+        };
+
+        let is_corrupted = |_| false;
+        assert_eq!(growth.factor(&tile((0, 0), "grass", &is_corrupted)), 0.0);
+
+        let is_corrupted = |(x, _)| x == 1;
+        assert_eq!(growth.factor(&tile((0, 0), "grass", &is_corrupted)), 0.875);
+    }
+
+    #[test]
+    fn spread_rates_fall_back_to_the_default() {
+        let rates = SpreadRates {
+            rates: HashMap::from([("sand".to_string(), 1.5)]),
+            default: 0.5,
+        };
+        let is_corrupted = |_| false;
+
+        assert_eq!(rates.factor(&tile((0, 0), "sand", &is_corrupted)), 1.5);
+        assert_eq!(rates.factor(&tile((0, 0), "grass", &is_corrupted)), 0.5);
+    }
+
+    #[test]
+    fn resistance_holds_out_in_proportion() {
+        let is_corrupted = |_| false;
+        let mut stone = tile((0, 0), "stone", &is_corrupted);
+        stone.properties.corruption_resistance = 0.25;
+
+        assert_eq!(Resistance.factor(&stone), 0.75);
+    }
+
+    #[test]
+    fn step_spreads_over_a_small_grid() {
+        let rules = CorruptionRules {
+            rules: vec![
+                Box::new(NeighbourThreshold {
+                    neighbourhood: Neighbourhood::VonNeumann,
+                    min: 1,
+                }),
+                Box::new(Resistance),
+            ],
+        };
+        // . W .
+        // . # E
+        // . H D
+        // Around the corrupted tile at (1, 1): E shares an edge, D only a
+        // corner, W resists completely and H resists half the time.
+        let is_corrupted = |position| position == (1, 1);
+        let grid = || {
+            [((2, 1), 0.0), ((1, 2), 1.0), ((2, 0), 0.0), ((1, 0), 0.5)].map(
+                |(position, corruption_resistance)| TileContext {
+                    position,
+                    id: "grass",
+                    properties: TileProperties {
+                        corruption_resistance,
+                        ..GRASS
+                    },
+                    is_corrupted: &is_corrupted,
+                },
+            )
+        };
+
+        let mut rng = WorldSeed(5).rng(CORRUPTION_STREAM);
+        let corrupted = rules.step(grid(), &mut rng);
+        assert!(corrupted.contains(&(2, 1)));
+        assert!(!corrupted.contains(&(1, 2)));
+        assert!(!corrupted.contains(&(2, 0)));
+
+        let mut rng = WorldSeed(5).rng(CORRUPTION_STREAM);
+        assert_eq!(rules.step(grid(), &mut rng), corrupted);
+    }
+
+    /// Grows corruption out from a single tile for `steps` steps, the way
+    /// `corruption_system` does, and returns the corrupted positions.
+    fn spread(rules: &CorruptionRules, seed: u64, steps: usize) -> Vec<(i32, i32)> {
+        let mut rng = WorldSeed(seed).rng(CORRUPTION_STREAM);
+        let mut corrupted: HashSet<(i32, i32)> = HashSet::from([(0, 0)]);
+
+        for _ in 0..steps {
+            let mut frontier: Vec<(i32, i32)> = corrupted
+                .iter()
+                .flat_map(|&(x, y)| {
+                    Neighbourhood::Moore
+                        .offsets()
+                        .iter()
+                        .map(move |(dx, dy)| (x + dx, y + dy))
+                })
+                .filter(|position| !corrupted.contains(position))
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            frontier.sort();
+
+            let before = corrupted.clone();
+            let is_corrupted = |position| before.contains(&position);
+            corrupted.extend(rules.step(
+                frontier.iter().map(|&position| TileContext {
+                    position,
+                    id: "grass",
+                    properties: GRASS,
+                    is_corrupted: &is_corrupted,
+                }),
+                &mut rng,
+            ));
+        }
+
+        let mut corrupted: Vec<(i32, i32)> = corrupted.into_iter().collect();
+        corrupted.sort();
+        corrupted
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_spread() {
+        let rules = CorruptionRules::default();
+        let spread_of_seed_1 = spread(&rules, 1, 40);
+
+        assert!(spread_of_seed_1.len() > 1);
+        assert_eq!(spread_of_seed_1, spread(&rules, 1, 40));
+        assert_ne!(spread_of_seed_1, spread(&rules, 2, 40));
+    }
+}
@@ -0,0 +1,119 @@
+// frontier.rs
+use bevy::prelude::*;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// The corruption frontier: loaded, healthy tiles with a corrupted tile in
+/// their Moore neighbourhood, by tile coordinate. Inserting, removing and
+/// sampling a random tile are all O(1).
+#[derive(Resource, Default)]
+pub struct PotentiallyCorruptedTiles {
+    tiles: Vec<(i32, i32)>,
+    /// Where each tile sits in `tiles`.
+    indices: HashMap<(i32, i32), usize>,
+}
+
+impl PotentiallyCorruptedTiles {
+    /// Adds `tile`, returning whether it was new.
+    pub fn insert(&mut self, tile: (i32, i32)) -> bool {
+        if self.indices.contains_key(&tile) {
+            return false;
+        }
+        self.indices.insert(tile, self.tiles.len());
+        self.tiles.push(tile);
+        true
+    }
+
+    /// Removes `tile`, returning whether it was there.
+    pub fn remove(&mut self, tile: (i32, i32)) -> bool {
+        let Some(index) = self.indices.remove(&tile) else {
+            return false;
+        };
+        self.tiles.swap_remove(index);
+        // The last tile moved into the gap
+        if let Some(&moved) = self.tiles.get(index) {
+            self.indices.insert(moved, index);
+        }
+        true
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<(i32, i32)> {
+        if self.tiles.is_empty() {
+            return None;
+        }
+        Some(self.tiles[rng.gen_range(0..self.tiles.len())])
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.tiles.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
+
+    #[test]
+    fn frontier_keeps_each_tile_once() {
+        let mut frontier = PotentiallyCorruptedTiles::default();
+
+        assert!(frontier.insert((1, 2)));
+        assert!(!frontier.insert((1, 2)));
+        assert!(frontier.insert((3, 4)));
+        assert_eq!(frontier.len(), 2);
+
+        assert!(frontier.remove((1, 2)));
+        assert!(!frontier.remove((1, 2)));
+        assert!(frontier.insert((1, 2)));
+        assert_eq!(frontier.len(), 2);
+    }
+
+    #[test]
+    fn frontier_remove_reindexes_the_moved_tile() {
+        let mut frontier = PotentiallyCorruptedTiles::default();
+        for tile in [(0, 0), (1, 0), (2, 0)] {
+            frontier.insert(tile);
+        }
+
+        // (2, 0) is swapped into the slot (0, 0) leaves
+        assert!(frontier.remove((0, 0)));
+        assert_eq!(frontier.indices[&(2, 0)], 0);
+        assert_eq!(frontier.iter().collect::<Vec<_>>(), [(2, 0), (1, 0)]);
+
+        assert!(frontier.remove((2, 0)));
+        assert_eq!(frontier.iter().collect::<Vec<_>>(), [(1, 0)]);
+        assert_eq!(frontier.indices[&(1, 0)], 0);
+
+        assert!(frontier.remove((1, 0)));
+        assert!(frontier.is_empty());
+        assert!(frontier.indices.is_empty());
+    }
+
+    #[test]
+    fn frontier_samples_every_tile() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut frontier = PotentiallyCorruptedTiles::default();
+        assert_eq!(frontier.sample(&mut rng), None);
+
+        for x in 0..10 {
+            frontier.insert((x, 0));
+        }
+        frontier.remove((4, 0));
+
+        let sampled: HashSet<(i32, i32)> = (0..1000)
+            .map(|_| frontier.sample(&mut rng).unwrap())
+            .collect();
+        assert_eq!(sampled, frontier.iter().collect());
+    }
+}
@@ -1,7 +1,21 @@
+pub mod biome;
+pub mod corruption_rules;
+pub mod frontier;
+pub mod noise;
+pub mod purification;
 pub mod tile_corruption;
 pub mod tile_gen;
+pub mod tile_registry;
 pub mod tile_render;
+pub mod world_seed;
 
+pub use biome::*;
+pub use corruption_rules::*;
+pub use frontier::*;
+pub use noise::*;
+pub use purification::*;
 pub use tile_corruption::*;
 pub use tile_gen::*;
+pub use tile_registry::*;
 pub use tile_render::*;
+pub use world_seed::*;
@@ -0,0 +1,68 @@
+// noise.rs
+
+/// Seeded 2D value noise: random values on an integer lattice, smoothly
+/// interpolated in between.
+#[derive(Clone, Copy, Debug)]
+pub struct ValueNoise {
+    seed: u64,
+}
+
+impl ValueNoise {
+    pub fn new(seed: u64) -> Self {
+        ValueNoise { seed }
+    }
+
+    /// A value from 0 to 1 for the lattice point `(x, y)`.
+    fn lattice(&self, x: i32, y: i32) -> f32 {
+        // SplitMix64 finalizer over the seed and coordinates
+        let mut hash = self.seed
+            ^ (x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ (y as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+
+        (hash >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Noise from 0 to 1 at `(x, y)`, with features about one unit across.
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (cell_x, cell_y) = (x0 as i32, y0 as i32);
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let bottom = lerp(
+            self.lattice(cell_x, cell_y),
+            self.lattice(cell_x + 1, cell_y),
+            tx,
+        );
+        let top = lerp(
+            self.lattice(cell_x, cell_y + 1),
+            self.lattice(cell_x + 1, cell_y + 1),
+            tx,
+        );
+        lerp(bottom, top, ty)
+    }
+
+    /// Layers `octaves` of noise, each twice the frequency and half the
+    /// strength of the last, so large shapes get rough edges. Stays between 0 and 1.
+    pub fn fractal(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        let mut total = 0.0;
+        let mut strength = 1.0;
+        let mut frequency = 1.0;
+        let mut max = 0.0;
+
+        for octave in 0..octaves {
+            // Offset each octave so their lattices don't line up
+            let offset = octave as f32 * 17.31;
+            total += self.sample(x * frequency + offset, y * frequency - offset) * strength;
+            max += strength;
+            strength *= 0.5;
+            frequency *= 2.0;
+        }
+
+        total / max
+    }
+}
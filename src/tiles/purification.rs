@@ -0,0 +1,102 @@
+// purification.rs
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_ecs_tilemap::prelude::*;
+
+use super::{
+    recede_corruption, refresh_frontier, LoadedChunks, PotentiallyCorruptedTiles, TileMap,
+    TileProperties, TileRegistry, TileType, TILE_SIZE,
+};
+
+/// Pixels a pulse reaches per second of the note that sent it.
+pub const PULSE_RADIUS_PER_SECOND: f32 = 32.0;
+
+/// Sent by `play_notes` and `play_judged_notes` as the song is played.
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub enum Purification {
+    /// A note marks the corrupted tiles within `radius` pixels of `center`.
+    Pulse { center: Vec2, radius: f32 },
+    /// Every note of the phrase was played; the marked tiles fall back a
+    /// stage and keep receding until they are healthy.
+    PhraseCompleted,
+    /// A note of the phrase couldn't be played; the marks are cleared.
+    PhraseBroken,
+}
+
+/// Corrupted tiles marked by the pulses of the phrase being played.
+#[derive(Resource, Default)]
+pub struct PendingPurification {
+    pub tiles: HashSet<(i32, i32)>,
+}
+
+pub fn purify_tiles(
+    mut purification_events: EventReader<Purification>,
+    mut pending: ResMut<PendingPurification>,
+    mut loaded_chunks: ResMut<LoadedChunks>,
+    tile_map: Res<TileMap>,
+    mut potentially_corrupted_tiles: ResMut<PotentiallyCorruptedTiles>,
+    mut tile_query: Query<(
+        &mut TileType,
+        &mut TileProperties,
+        &mut TileTextureIndex,
+        &mut TileColor,
+    )>,
+    registry: Res<TileRegistry>,
+) {
+    for event in purification_events.read() {
+        match *event {
+            Purification::Pulse { center, radius } => {
+                let min = ((center - radius) / TILE_SIZE).floor().as_ivec2();
+                let max = ((center + radius) / TILE_SIZE).ceil().as_ivec2();
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        let position = Vec2::new(x as f32, y as f32) * TILE_SIZE;
+                        if position.distance(center) <= radius && loaded_chunks.is_corrupted((x, y))
+                        {
+                            pending.tiles.insert((x, y));
+                        }
+                    }
+                }
+            }
+            Purification::PhraseBroken => pending.tiles.clear(),
+            Purification::PhraseCompleted => {
+                if pending.tiles.is_empty() {
+                    continue;
+                }
+                info!("Purified {} tiles", pending.tiles.len());
+
+                let mut purified = Vec::new();
+                for &position in &pending.tiles {
+                    let Some(shown) = recede_corruption(&mut loaded_chunks, &registry, position)
+                    else {
+                        continue;
+                    };
+                    if !loaded_chunks.is_corrupted(position) {
+                        purified.push(position);
+                    }
+
+                    // Unloaded tiles come back from `LoadedChunks` when their chunk loads
+                    let Some(&entity) = tile_map.tiles.get(&position) else {
+                        continue;
+                    };
+                    if let Ok((mut tile_type, mut properties, mut texture_index, mut color)) =
+                        tile_query.get_mut(entity)
+                    {
+                        *tile_type = shown;
+                        *properties = registry.get(*tile_type).properties();
+                        *texture_index = tile_type.texture_index();
+                        *color = registry.get(*tile_type).color();
+                    }
+                }
+                refresh_frontier(
+                    &tile_map,
+                    &loaded_chunks,
+                    purified,
+                    &mut potentially_corrupted_tiles,
+                );
+
+                pending.tiles.clear();
+            }
+        }
+    }
+}
@@ -1,88 +1,247 @@
 // tiles.rs
 use crate::{
+    audio::BeatEvent,
     find_and_push_neighbors,
-    player::CorruptedTileTexture,
-    tiles::{TileType, TILE_SIZE},
-    PotentiallyCorruptedTiles, TileMap,
+    rhythm::{Grade, NoteJudged},
+    tiles::TileType,
 };
 use bevy::prelude::*;
-use rand::prelude::SliceRandom;
+use bevy_ecs_tilemap::prelude::*;
+use rand::Rng;
 
-use super::Tile;
+use super::{
+    refresh_frontier, ChunkTiles, CorruptionRng, CorruptionRules, LoadedChunks, Tile, TileContext,
+    TileProperties, TileRegistry,
+};
+
+/// Most frontier tiles judged in one corruption tick.
+pub const MAX_TILES_PER_TICK: usize = 4096;
+/// Chance that a corrupted tile advances a stage in a corruption tick.
+pub const STAGE_ADVANCE_CHANCE: f32 = 0.2;
+/// Beats between corruption ticks at the start of a run.
+pub const START_INTERVAL: f32 = 12.0;
+
+/// The record a corrupted tile keeps of itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorruptionState {
+    /// Id of the tile's kind before it was corrupted, restored when it is
+    /// purified. Kept by id so it survives the tile set being swapped.
+    pub original: String,
+    /// How far the corruption has taken hold, starting at 1. Each stage is
+    /// shown by its own tile in `TileRegistry::corruption_stages`.
+    pub stage: u8,
+    /// Set once the tile is purified. It then falls back a stage every
+    /// corruption tick instead of advancing, until it is healthy again.
+    pub receding: bool,
+}
+
+impl CorruptionState {
+    pub fn new(original: impl Into<String>) -> Self {
+        CorruptionState {
+            original: original.into(),
+            stage: 1,
+            receding: false,
+        }
+    }
+}
+
+/// Moves the corrupted tile at `position` back a stage and sets it receding.
+/// Returns the type the tile now shows: the stage below, or its original type
+/// once it falls back past the first stage and is healthy again.
+pub fn recede_corruption(
+    loaded_chunks: &mut LoadedChunks,
+    registry: &TileRegistry,
+    position: (i32, i32),
+) -> Option<TileType> {
+    let state = loaded_chunks.corruption_mut(position)?;
+    let stage = state.stage.min(registry.max_corruption_stage());
+    if stage <= 1 {
+        // Stays corrupted if the tile set no longer has the original kind
+        let original = registry.find(&state.original)?;
+        loaded_chunks.clear_corrupted(position);
+        return Some(original);
+    }
 
+    state.stage = stage - 1;
+    state.receding = true;
+    Some(registry.corruption_stage(state.stage))
+}
+
+/// Counts down beats of the `BeatClock` until the next tile is corrupted.
 #[derive(Resource, Clone)]
 pub struct CorruptionTimer {
-    pub timer: Timer,
+    /// Beats between corruption ticks. Shrinks by 5% after every tick.
+    pub interval: f32,
+    pub beats_left: f32,
+}
+
+impl CorruptionTimer {
+    pub fn new(interval: f32) -> Self {
+        CorruptionTimer {
+            interval,
+            beats_left: interval,
+        }
+    }
 }
 
+impl Default for CorruptionTimer {
+    fn default() -> Self {
+        CorruptionTimer::new(START_INTERVAL)
+    }
+}
+
+/// Every `CorruptionTimer` tick, corrupted tiles may advance a stage, or fall
+/// back one if receding, and each healthy tile on the frontier may be
+/// corrupted according to the `CorruptionRules`.
 pub fn corruption_system(
-    mut commands: Commands,
-    time: Res<Time>,
+    mut beat_events: EventReader<BeatEvent>,
     mut corruption_timer: ResMut<CorruptionTimer>,
-    mut potentially_corrupted_tiles: ResMut<PotentiallyCorruptedTiles>,
-    tile_query: Query<(&mut TileType, &Tile)>,
-    mut tile_map: ResMut<TileMap>,
-    asset_server: Res<AssetServer>,
+    mut tile_query: Query<(
+        &mut TileType,
+        &mut TileProperties,
+        &Tile,
+        &mut TileTextureIndex,
+        &mut TileColor,
+    )>,
+    mut corruption_rng: ResMut<CorruptionRng>,
+    mut chunk_tiles: ChunkTiles,
+    registry: Res<TileRegistry>,
+    rules: Res<CorruptionRules>,
 ) {
-    corruption_timer.timer.tick(time.delta());
-
-    if corruption_timer.timer.just_finished() && !potentially_corrupted_tiles.tiles.is_empty() {
-        loop {
-            if let Some(entity) = potentially_corrupted_tiles
-                .tiles
-                .choose(&mut rand::thread_rng())
-            {
-                if let Ok((tile_type, old_tile)) = tile_query.get(*entity) {
-                    if !tile_type.is_corrupted_tile() {
-                        commands.entity(*entity).despawn();
-
-                        let new_entity = commands
-                            .spawn((
-                                SpriteBundle {
-                                    texture: asset_server.load("corrupted_tile_1.png"),
-                                    sprite: Sprite {
-                                        custom_size: Some(Vec2::splat(TILE_SIZE)),
-                                        ..default()
-                                    },
-                                    transform: Transform::from_xyz(
-                                        old_tile.x * TILE_SIZE,
-                                        old_tile.y * TILE_SIZE,
-                                        1.0,
-                                    ),
-                                    ..default()
-                                },
-                                old_tile.clone(),
-                                TileType::Corruption {
-                                    png: "corrupted_tile_1.png".to_string(),
-                                },
-                            ))
-                            .id();
-
-                        tile_map
-                            .tiles
-                            .insert((old_tile.x as i32, old_tile.y as i32), new_entity);
-
-                        info!("+1 corrupt tile");
-                        find_and_push_neighbors(
-                            &tile_map,
-                            &old_tile,
-                            &mut potentially_corrupted_tiles,
-                        );
-                        break;
-                    } else {
-                        info!("Query failed");
-                        if potentially_corrupted_tiles.tiles.is_empty() {
-                            break;
-                        }
-                    }
-                }
-            }
+    corruption_timer.beats_left -= beat_events.read().count() as f32;
+
+    if corruption_timer.beats_left > 0.0 {
+        return;
+    }
+
+    corruption_timer.interval *= 0.95;
+    debug!("{:?} beats", corruption_timer.interval);
+
+    corruption_timer.beats_left = corruption_timer.interval;
+    let ChunkTiles {
+        loaded_chunks,
+        tile_map,
+        potentially_corrupted_tiles,
+    } = &mut chunk_tiles;
+
+    // Sorted so the stages, like the spread, stay seeded
+    let mut corrupted_tiles: Vec<(i32, i32)> = loaded_chunks
+        .chunks
+        .keys()
+        .filter_map(|chunk| loaded_chunks.corruption_tiles.get(chunk))
+        .flat_map(|tiles| tiles.keys().copied())
+        .collect();
+    corrupted_tiles.sort();
+
+    let mut purified = Vec::new();
+    for position in corrupted_tiles {
+        let Some(state) = loaded_chunks.corruption_mut(position) else {
+            continue;
+        };
+        let shown = if state.receding {
+            recede_corruption(loaded_chunks, &registry, position)
+        } else if state.stage < registry.max_corruption_stage()
+            && corruption_rng.0.gen::<f32>() < STAGE_ADVANCE_CHANCE
+        {
+            state.stage += 1;
+            Some(registry.corruption_stage(state.stage))
+        } else {
+            None
+        };
+        let Some(shown) = shown else {
+            continue;
+        };
+
+        if !loaded_chunks.is_corrupted(position) {
+            purified.push(position);
+        }
+        let Some(&entity) = tile_map.tiles.get(&position) else {
+            continue;
+        };
+        if let Ok((mut tile_type, mut properties, _, mut texture_index, mut color)) =
+            tile_query.get_mut(entity)
+        {
+            *tile_type = shown;
+            *properties = registry.get(*tile_type).properties();
+            *texture_index = tile_type.texture_index();
+            *color = registry.get(*tile_type).color();
         }
+    }
+    refresh_frontier(
+        tile_map,
+        loaded_chunks,
+        purified,
+        potentially_corrupted_tiles,
+    );
+
+    if potentially_corrupted_tiles.is_empty() {
+        return;
+    }
 
-        let old_timer = corruption_timer.timer.duration().mul_f32(0.95);
-        info!("{:?}", old_timer);
+    // On a long frontier, judge a random sample so a tick costs the same on
+    // any size of stage
+    let mut candidates: Vec<(i32, i32)> = if potentially_corrupted_tiles.len() <= MAX_TILES_PER_TICK
+    {
+        potentially_corrupted_tiles.iter().collect()
+    } else {
+        (0..MAX_TILES_PER_TICK)
+            .filter_map(|_| potentially_corrupted_tiles.sample(&mut corruption_rng.0))
+            .collect()
+    };
+    candidates.sort();
+    candidates.dedup();
 
-        corruption_timer.timer.set_duration(old_timer);
-        corruption_timer.timer.reset();
+    let is_corrupted = |position: (i32, i32)| loaded_chunks.is_corrupted(position);
+    let tiles = candidates
+        .iter()
+        .filter_map(|position| tile_map.tiles.get(position))
+        .filter_map(|&entity| tile_query.get(entity).ok())
+        .map(|(tile_type, properties, tile, _, _)| TileContext {
+            position: (tile.x as i32, tile.y as i32),
+            id: &registry.get(*tile_type).id,
+            properties: *properties,
+            is_corrupted: &is_corrupted,
+        });
+    let corrupted = rules.step(tiles, &mut corruption_rng.0);
+
+    for position in &corrupted {
+        let Some(&entity) = tile_map.tiles.get(position) else {
+            continue;
+        };
+        let Ok((mut tile_type, mut properties, tile, mut texture_index, mut color)) =
+            tile_query.get_mut(entity)
+        else {
+            continue;
+        };
+
+        // The tile stays in place; only its type, properties and look change
+        loaded_chunks.set_corrupted(
+            *position,
+            CorruptionState::new(&registry.get(*tile_type).id),
+        );
+        *tile_type = registry.corruption_stage(1);
+        *properties = registry.get(*tile_type).properties();
+        *texture_index = tile_type.texture_index();
+        *color = registry.get(*tile_type).color();
+        potentially_corrupted_tiles.remove(*position);
+        find_and_push_neighbors(tile_map, loaded_chunks, tile, potentially_corrupted_tiles);
+    }
+
+    if !corrupted.is_empty() {
+        info!("+{} corrupt tiles", corrupted.len());
+    }
+}
+
+/// Missed notes let the corruption close in a beat sooner; perfect ones hold it back.
+pub fn corruption_rhythm_response(
+    mut note_judged: EventReader<NoteJudged>,
+    mut corruption_timer: ResMut<CorruptionTimer>,
+) {
+    for judged in note_judged.read() {
+        match judged.grade {
+            Grade::Perfect => corruption_timer.beats_left += 0.5,
+            Grade::Good => {}
+            Grade::Miss => corruption_timer.beats_left -= 1.0,
+        }
     }
 }
@@ -1,28 +1,24 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_ecs_tilemap::prelude::*;
 use rand::Rng;
 use std::collections::HashMap;
 
+use super::{
+    chunk_of, BiomeTable, ChunkTiles, CorruptionState, LoadedChunks, Neighbourhood,
+    PotentiallyCorruptedTiles, TileRegistry, TileTextures, TileType, ValueNoise, WorldSeed,
+    CHUNK_SIZE, STAGE_STREAM,
+};
+
 // Constants and types related to tile generation
 pub const TILE_SIZE: f32 = 8.0;
-pub const STAGE_SIZE: (i32, i32) = (64, 64); // 256x256 tiles
-
-#[derive(Component, Clone, PartialEq)]
-pub enum TileType {
-    Green { png: String },
-    Grass { png: String },
-    Flower { png: String },
-    Sand { png: String },
-    Corruption { png: String },
-}
-
-impl TileType {
-    pub fn is_corrupted_tile(&self) -> bool {
-        match self {
-            TileType::Corruption { .. } => true,
-            _ => false,
-        }
-    }
-}
+/// The tile the player starts on.
+pub const SPAWN_TILE: IVec2 = IVec2::new(32, 32);
+/// Chunks this many chunks or fewer from the spawn chunk start without corruption.
+const CLEAN_CHUNKS: i32 = 1;
+/// Chance that corruption starts somewhere in a newly generated chunk.
+const CORRUPTION_SEED_CHANCE: f64 = 0.2;
 
 #[derive(Component, Clone)]
 pub struct Tile {
@@ -30,163 +26,332 @@ pub struct Tile {
     pub y: f32,
 }
 
+/// Tiles of the loaded chunks, by tile coordinate.
 #[derive(Resource)]
 pub struct TileMap {
     pub tiles: HashMap<(i32, i32), Entity>,
 }
 
-#[derive(Resource)]
-pub struct PotentiallyCorruptedTiles {
-    pub tiles: Vec<Entity>,
+/// The noise fields the terrain is read from, sampled at world tile
+/// coordinates so every chunk comes out the same however it is reached.
+#[derive(Resource, Clone, Copy)]
+pub struct StageNoise {
+    pub elevation: ValueNoise,
+    pub moisture: ValueNoise,
+    /// Sampled on whole tiles, where value noise is uncorrelated, to mix the
+    /// tiles within a biome.
+    pub variety: ValueNoise,
 }
 
-pub fn generate_stage(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut tile_map: ResMut<TileMap>,
-    mut potentially_corrupted_tiles: ResMut<PotentiallyCorruptedTiles>,
-) {
-    let mut rng = rand::thread_rng();
-    let mut total_corrupted = 0;
-    let max_corruption = 24;
-
-    for y in 0..STAGE_SIZE.1 {
-        for x in 0..STAGE_SIZE.0 {
-            let tile_entity: Entity = match rng.gen_range(0.0..1.0) {
-                a if a <= 0.05
-                    && total_corrupted < max_corruption
-                    && (x == 0 || y == 0 || x == STAGE_SIZE.0 - 1 || y == STAGE_SIZE.1 - 1) =>
-                {
-                    total_corrupted += 1;
-                    info!("Hitting init corrupts: ");
-
-                    spawn_tile(
-                        &mut commands,
-                        &asset_server,
-                        &mut potentially_corrupted_tiles,
-                        Tile {
-                            x: x as f32,
-                            y: y as f32,
-                        },
-                        TileType::Corruption {
-                            png: "corrupted_tile_1.png".to_string(),
-                        },
-                        &tile_map,
-                    )
-                }
-                a if a <= 0.08 => spawn_tile(
-                    &mut commands,
-                    &asset_server,
-                    &mut potentially_corrupted_tiles,
-                    Tile {
-                        x: x as f32,
-                        y: y as f32,
-                    },
-                    TileType::Grass {
-                        png: "tile_0001.png".to_string(),
-                    },
-                    &tile_map,
-                ),
-                a if a <= 0.10 => spawn_tile(
-                    &mut commands,
-                    &asset_server,
-                    &mut potentially_corrupted_tiles,
-                    Tile {
-                        x: x as f32,
-                        y: y as f32,
-                    },
-                    TileType::Flower {
-                        png: "tile_0002.png".to_string(),
-                    },
-                    &tile_map,
-                ),
-                a if a <= 0.12 => spawn_tile(
-                    &mut commands,
-                    &asset_server,
-                    &mut potentially_corrupted_tiles,
-                    Tile {
-                        x: x as f32,
-                        y: y as f32,
-                    },
-                    TileType::Sand {
-                        png: "tile_0003.png".to_string(),
+impl StageNoise {
+    pub fn new(seed: WorldSeed) -> Self {
+        let mut rng = seed.rng(STAGE_STREAM);
+        StageNoise {
+            elevation: ValueNoise::new(rng.gen()),
+            moisture: ValueNoise::new(rng.gen()),
+            variety: ValueNoise::new(rng.gen()),
+        }
+    }
+
+    pub fn tile_at(
+        &self,
+        x: i32,
+        y: i32,
+        biome_table: &BiomeTable,
+        registry: &TileRegistry,
+    ) -> TileType {
+        let (noise_x, noise_y) = (
+            x as f32 * biome_table.frequency,
+            y as f32 * biome_table.frequency,
+        );
+        biome_table.tile_at(
+            self.elevation
+                .fractal(noise_x, noise_y, biome_table.octaves),
+            self.moisture.fractal(noise_x, noise_y, biome_table.octaves),
+            self.variety.sample(x as f32, y as f32),
+            registry,
+        )
+    }
+}
+
+/// Picks where corruption starts in a chunk the first time it is generated.
+/// Chunks around `SPAWN_TILE` start clean so the player isn't boxed in.
+pub fn seed_corruption(seed: WorldSeed, chunk: IVec2) -> HashSet<(i32, i32)> {
+    let mut corrupted = HashSet::new();
+    let spawn_chunk = chunk_of(SPAWN_TILE);
+    if (chunk - spawn_chunk).abs().max_element() <= CLEAN_CHUNKS {
+        return corrupted;
+    }
+
+    let mut rng = seed.rng_at(STAGE_STREAM, chunk);
+    if rng.gen_bool(CORRUPTION_SEED_CHANCE) {
+        let origin = chunk * CHUNK_SIZE;
+        corrupted.insert((
+            origin.x + rng.gen_range(0..CHUNK_SIZE),
+            origin.y + rng.gen_range(0..CHUNK_SIZE),
+        ));
+    }
+    corrupted
+}
+
+/// What chunks are generated from: the tile set, and the noise and biomes
+/// the stage is laid out by.
+#[derive(SystemParam)]
+pub struct ChunkGenerator<'w> {
+    pub tile_textures: Res<'w, TileTextures>,
+    pub registry: Res<'w, TileRegistry>,
+    pub stage_noise: Res<'w, StageNoise>,
+    pub biome_table: Res<'w, BiomeTable>,
+    pub seed: Res<'w, WorldSeed>,
+}
+
+/// Spawns the tiles of `chunk` under a new chunk entity, corrupting the ones
+/// recorded in `LoadedChunks::corruption_tiles`.
+pub fn generate_chunk(
+    commands: &mut Commands,
+    generator: &ChunkGenerator,
+    chunk: IVec2,
+    chunk_tiles: &mut ChunkTiles,
+) -> Entity {
+    let ChunkGenerator {
+        tile_textures,
+        registry,
+        stage_noise,
+        biome_table,
+        seed,
+    } = generator;
+    let ChunkTiles {
+        loaded_chunks,
+        tile_map,
+        potentially_corrupted_tiles,
+    } = chunk_tiles;
+
+    if !loaded_chunks.corruption_tiles.contains_key(&chunk) {
+        let seeded = seed_corruption(**seed, chunk)
+            .into_iter()
+            .map(|(x, y)| {
+                let original = stage_noise.tile_at(x, y, biome_table, registry);
+                ((x, y), CorruptionState::new(&registry.get(original).id))
+            })
+            .collect();
+        loaded_chunks.corruption_tiles.insert(chunk, seeded);
+    }
+    let corrupted = loaded_chunks.corruption_tiles[&chunk].clone();
+
+    // Each chunk is one tilemap, drawn in a single batch
+    let chunk_entity = commands.spawn_empty().id();
+    let origin = chunk * CHUNK_SIZE;
+    let map_size = TilemapSize {
+        x: CHUNK_SIZE as u32,
+        y: CHUNK_SIZE as u32,
+    };
+    let mut tile_storage = TileStorage::empty(map_size);
+
+    for y in origin.y..origin.y + CHUNK_SIZE {
+        for x in origin.x..origin.x + CHUNK_SIZE {
+            let tile_type = if let Some(state) = corrupted.get(&(x, y)) {
+                registry.corruption_stage(state.stage)
+            } else {
+                stage_noise.tile_at(x, y, biome_table, registry)
+            };
+
+            let position = TilePos {
+                x: (x - origin.x) as u32,
+                y: (y - origin.y) as u32,
+            };
+            let tile_entity = commands
+                .spawn((
+                    TileBundle {
+                        position,
+                        tilemap_id: TilemapId(chunk_entity),
+                        texture_index: tile_type.texture_index(),
+                        color: registry.get(tile_type).color(),
+                        ..default()
                     },
-                    &tile_map,
-                ),
-                _ => spawn_tile(
-                    &mut commands,
-                    &asset_server,
-                    &mut potentially_corrupted_tiles,
                     Tile {
                         x: x as f32,
                         y: y as f32,
                     },
-                    TileType::Green {
-                        png: "tile_0000.png".to_string(),
-                    },
-                    &tile_map,
-                ),
-            };
-
+                    tile_type,
+                    registry.get(tile_type).properties(),
+                ))
+                .id();
+            commands.entity(chunk_entity).add_child(tile_entity);
+            tile_storage.set(&position, tile_entity);
             tile_map.tiles.insert((x, y), tile_entity);
         }
     }
-}
 
-fn spawn_tile(
-    commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
-    potentially_corrupted_tiles: &mut ResMut<PotentiallyCorruptedTiles>,
-    tile: Tile,
-    tile_type: TileType,
-    tile_map: &ResMut<TileMap>,
-) -> Entity {
-    let png = match &tile_type {
-        TileType::Green { png, .. } => png,
-        TileType::Grass { png, .. } => png,
-        TileType::Flower { png, .. } => png,
-        TileType::Sand { png, .. } => png,
-        TileType::Corruption { png } => png,
-    };
+    // Corruption spreads from this chunk into its loaded neighbours, and from
+    // neighbouring chunks, loaded or not, into this one
+    for &(x, y) in corrupted.keys() {
+        find_and_push_neighbors(
+            tile_map,
+            loaded_chunks,
+            &Tile {
+                x: x as f32,
+                y: y as f32,
+            },
+            potentially_corrupted_tiles,
+        );
+    }
+    for y in origin.y..origin.y + CHUNK_SIZE {
+        for x in origin.x..origin.x + CHUNK_SIZE {
+            let on_edge = x == origin.x
+                || y == origin.y
+                || x == origin.x + CHUNK_SIZE - 1
+                || y == origin.y + CHUNK_SIZE - 1;
+            if !on_edge || corrupted.contains_key(&(x, y)) {
+                continue;
+            }
 
-    if tile_type.is_corrupted_tile() {
-        find_and_push_neighbors(tile_map, &tile, potentially_corrupted_tiles);
+            let next_to_corruption = Neighbourhood::Moore
+                .offsets()
+                .iter()
+                .any(|(dx, dy)| loaded_chunks.is_corrupted((x + dx, y + dy)));
+            if next_to_corruption {
+                potentially_corrupted_tiles.insert((x, y));
+            }
+        }
     }
 
-    let entity = commands
-        .spawn((
-            SpriteBundle {
-                texture: asset_server.load(png),
-                sprite: Sprite {
-                    custom_size: Some(Vec2::splat(TILE_SIZE)),
-                    ..default()
-                },
-                transform: Transform::from_translation(Vec3::new(
-                    tile.x * TILE_SIZE,
-                    tile.y * TILE_SIZE,
-                    0.0,
-                )),
-                ..default()
-            },
-            tile,
-            tile_type,
-        ))
-        .id();
+    let tile_size = TilemapTileSize {
+        x: TILE_SIZE,
+        y: TILE_SIZE,
+    };
+    commands.entity(chunk_entity).insert(TilemapBundle {
+        grid_size: tile_size.into(),
+        size: map_size,
+        storage: tile_storage,
+        texture: TilemapTexture::Vector(tile_textures.handles.clone()),
+        tile_size,
+        // Tile positions are centred on the tilemap's translation
+        transform: Transform::from_translation((origin.as_vec2() * TILE_SIZE).extend(0.0)),
+        ..default()
+    });
 
-    entity
+    loaded_chunks.chunks.insert(chunk, chunk_entity);
+    chunk_entity
 }
 
+/// Adds the loaded, healthy neighbours of `transform` to the frontier.
 pub fn find_and_push_neighbors(
-    tile_map: &ResMut<TileMap>,
+    tile_map: &TileMap,
+    loaded_chunks: &LoadedChunks,
     transform: &Tile,
-    potentially_corrupted_tiles: &mut ResMut<PotentiallyCorruptedTiles>,
+    potentially_corrupted_tiles: &mut PotentiallyCorruptedTiles,
+) {
+    // Push the wider neighbourhood so rules can use either
+    for (dx, dy) in Neighbourhood::Moore.offsets() {
+        let neighbor = (transform.x as i32 + dx, transform.y as i32 + dy);
+        if tile_map.tiles.contains_key(&neighbor) && !loaded_chunks.is_corrupted(neighbor) {
+            potentially_corrupted_tiles.insert(neighbor);
+        }
+    }
+}
+
+/// Brings the frontier up to date at `positions` after tiles there were
+/// purified: each is on it if loaded, healthy and next to corruption.
+pub fn refresh_frontier(
+    tile_map: &TileMap,
+    loaded_chunks: &LoadedChunks,
+    positions: impl IntoIterator<Item = (i32, i32)>,
+    potentially_corrupted_tiles: &mut PotentiallyCorruptedTiles,
 ) {
-    for (dx, dy) in &[(0, 1), (0, -1), (1, 0), (-1, 0)] {
-        if let Some(&neighbor_entity) = tile_map
-            .tiles
-            .get(&(transform.x as i32 + dx, transform.y as i32 + dy))
+    // Purified tiles and their neighbours may have left the frontier, and
+    // purified tiles may still border corruption. Sorted so the frontier
+    // order, and so the spread, stays seeded.
+    let mut affected: Vec<(i32, i32)> = positions
+        .into_iter()
+        .flat_map(|(x, y)| {
+            Neighbourhood::Moore
+                .offsets()
+                .iter()
+                .map(move |(dx, dy)| (x + dx, y + dy))
+                .chain([(x, y)])
+        })
+        .collect();
+    affected.sort();
+    affected.dedup();
+
+    for position in affected {
+        let (x, y) = position;
+        let borders_corruption = Neighbourhood::Moore
+            .offsets()
+            .iter()
+            .any(|(dx, dy)| loaded_chunks.is_corrupted((x + dx, y + dy)));
+        if tile_map.tiles.contains_key(&position)
+            && !loaded_chunks.is_corrupted(position)
+            && borders_corruption
         {
-            potentially_corrupted_tiles.tiles.push(neighbor_entity);
+            potentially_corrupted_tiles.insert(position);
+        } else {
+            potentially_corrupted_tiles.remove(position);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiles::tile_registry_from_ron;
+
+    fn layout(seed: u64) -> Vec<TileType> {
+        let registry =
+            tile_registry_from_ron(include_bytes!("../../assets/tiles/default.tiles.ron")).unwrap();
+        let biome_table = BiomeTable::default();
+        let noise = StageNoise::new(WorldSeed(seed));
+
+        (0..64)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .map(|(x, y)| noise.tile_at(x, y, &biome_table, &registry))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_stage() {
+        assert_eq!(layout(1), layout(1));
+        assert_ne!(layout(1), layout(2));
+    }
+
+    #[test]
+    fn corruption_stages_look_apart() {
+        let registry =
+            tile_registry_from_ron(include_bytes!("../../assets/tiles/default.tiles.ron")).unwrap();
+        let looks: Vec<(&str, [f32; 3])> = registry
+            .corruption_stages
+            .iter()
+            .map(|&stage| {
+                let definition = registry.get(stage);
+                (definition.texture.as_str(), definition.tint)
+            })
+            .collect();
+
+        for (index, look) in looks.iter().enumerate() {
+            assert!(
+                !looks[..index].contains(look),
+                "stage {} looks like another",
+                index + 1
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_corruption_does_not_depend_on_load_order() {
+        let chunks: Vec<IVec2> = (-8..8)
+            .flat_map(|y| (-8..8).map(move |x| IVec2::new(x, y)))
+            .collect();
+        let seeded = |seed: u64, chunks: &mut dyn Iterator<Item = &IVec2>| {
+            let mut tiles: Vec<(i32, i32)> = chunks
+                .flat_map(|&chunk| seed_corruption(WorldSeed(seed), chunk))
+                .collect();
+            tiles.sort();
+            tiles
+        };
+
+        let forwards = seeded(3, &mut chunks.iter());
+        assert!(!forwards.is_empty());
+        assert_eq!(forwards, seeded(3, &mut chunks.iter().rev()));
+        assert_ne!(forwards, seeded(4, &mut chunks.iter()));
+    }
+}
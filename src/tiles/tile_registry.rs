@@ -0,0 +1,272 @@
+// tile_registry.rs
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::{BoxedFuture, HashMap};
+use bevy_ecs_tilemap::prelude::*;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{ChunkTiles, TileTextures};
+
+/// A kind of tile, as described in a `.tiles.ron` file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct TileDefinition {
+    pub id: String,
+    pub texture: String,
+    #[serde(default = "default_true")]
+    pub walkable: bool,
+    /// Multiplies the player's speed while standing on the tile.
+    #[serde(default = "default_one")]
+    pub speed: f32,
+    /// From 0 to 1, how strongly the tile holds out against corruption.
+    #[serde(default)]
+    pub corruption_resistance: f32,
+    /// How often the tile is picked among the other tiles of its biome.
+    #[serde(default = "default_one")]
+    pub spawn_weight: f32,
+    /// Red, green and blue the texture is multiplied by, so tiles can share
+    /// a texture and still look apart.
+    #[serde(default = "default_tint")]
+    pub tint: [f32; 3],
+}
+
+impl TileDefinition {
+    pub fn properties(&self) -> TileProperties {
+        TileProperties {
+            walkable: self.walkable,
+            speed: self.speed,
+            corruption_resistance: self.corruption_resistance,
+        }
+    }
+
+    pub fn color(&self) -> TileColor {
+        let [red, green, blue] = self.tint;
+        TileColor(Color::rgb(red, green, blue))
+    }
+}
+
+/// The gameplay side of a tile's definition, kept on every tile entity so
+/// systems can query it directly.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct TileProperties {
+    pub walkable: bool,
+    pub speed: f32,
+    /// Chance from 0 to 1 that the tile holds out when corruption reaches it.
+    pub corruption_resistance: f32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_tint() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+/// On-disk layout of a `.tiles.ron` file.
+#[derive(Deserialize)]
+struct TileFile {
+    /// Ids of the tiles corruption turns others into, one per stage from
+    /// the first to the last.
+    corruption_stages: Vec<String>,
+    tiles: Vec<TileDefinition>,
+}
+
+/// Every kind of tile, in the order tilemaps index their textures.
+#[derive(Asset, Resource, TypePath, Clone, Debug)]
+pub struct TileRegistry {
+    pub definitions: Vec<TileDefinition>,
+    /// The tile shown at each stage of corruption, from stage 1.
+    pub corruption_stages: Vec<TileType>,
+    ids: HashMap<String, TileType>,
+}
+
+impl TileRegistry {
+    pub fn new(
+        definitions: Vec<TileDefinition>,
+        corruption_stages: &[String],
+    ) -> Result<TileRegistry, TileLoaderError> {
+        let mut ids = HashMap::new();
+        for (index, definition) in definitions.iter().enumerate() {
+            if ids
+                .insert(definition.id.clone(), TileType(index as u16))
+                .is_some()
+            {
+                return Err(TileLoaderError::DuplicateId(definition.id.clone()));
+            }
+        }
+
+        if corruption_stages.is_empty() || corruption_stages.len() > u8::MAX as usize {
+            return Err(TileLoaderError::CorruptionStages(corruption_stages.len()));
+        }
+        let corruption_stages = corruption_stages
+            .iter()
+            .map(|id| {
+                ids.get(id)
+                    .copied()
+                    .ok_or_else(|| TileLoaderError::UnknownId(id.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(TileRegistry {
+            definitions,
+            corruption_stages,
+            ids,
+        })
+    }
+
+    pub fn get(&self, tile_type: TileType) -> &TileDefinition {
+        &self.definitions[tile_type.0 as usize]
+    }
+
+    pub fn find(&self, id: &str) -> Option<TileType> {
+        self.ids.get(id).copied()
+    }
+
+    pub fn is_corrupted(&self, tile_type: TileType) -> bool {
+        self.corruption_stages.contains(&tile_type)
+    }
+
+    pub fn max_corruption_stage(&self) -> u8 {
+        self.corruption_stages.len() as u8
+    }
+
+    /// The tile shown at a stage of corruption. Stages past the last one, left
+    /// by a tile set with more of them, show as the last.
+    pub fn corruption_stage(&self, stage: u8) -> TileType {
+        let index = (stage.max(1) as usize).min(self.corruption_stages.len()) - 1;
+        self.corruption_stages[index]
+    }
+
+    /// Picks one of the tiles `ids` by their spawn weights, `roll` being a
+    /// number from 0 to 1. Unknown ids are skipped with a warning.
+    pub fn pick(&self, ids: &[String], roll: f32) -> Option<TileType> {
+        let candidates: Vec<TileType> = ids
+            .iter()
+            .filter_map(|id| {
+                let tile_type = self.find(id);
+                if tile_type.is_none() {
+                    warn!("Unknown tile {id:?}");
+                }
+                tile_type
+            })
+            .collect();
+
+        let total: f32 = candidates
+            .iter()
+            .map(|&tile_type| self.get(tile_type).spawn_weight)
+            .sum();
+        let mut remaining = roll * total;
+        for &tile_type in &candidates {
+            remaining -= self.get(tile_type).spawn_weight;
+            if remaining < 0.0 {
+                return Some(tile_type);
+            }
+        }
+        candidates.last().copied()
+    }
+}
+
+/// A tile's kind, looked up in the `TileRegistry`.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileType(pub u16);
+
+impl TileType {
+    /// Tilemaps hold the registry's textures in order, one per tile kind.
+    pub fn texture_index(self) -> TileTextureIndex {
+        TileTextureIndex(self.0 as u32)
+    }
+}
+
+/// The tile set in use. Point this at another handle to swap tile sets.
+#[derive(Resource)]
+pub struct CurrentTileSet(pub Handle<TileRegistry>);
+
+/// Puts the tile set into the `TileRegistry` resource whenever it loads or
+/// changes. Loaded chunks are regenerated, so edits show up while playing.
+pub fn apply_tile_set(
+    mut commands: Commands,
+    current_tile_set: Res<CurrentTileSet>,
+    tile_sets: Res<Assets<TileRegistry>>,
+    mut tile_set_events: EventReader<AssetEvent<TileRegistry>>,
+    asset_server: Res<AssetServer>,
+    mut chunk_tiles: ChunkTiles,
+) {
+    let reloaded = tile_set_events.read().fold(false, |reloaded, event| {
+        reloaded
+            || event.is_loaded_with_dependencies(&current_tile_set.0)
+            || event.is_modified(&current_tile_set.0)
+    });
+
+    if !reloaded && !current_tile_set.is_changed() {
+        return;
+    }
+
+    let Some(registry) = tile_sets.get(&current_tile_set.0) else {
+        return;
+    };
+
+    info!("Loaded {} tile kinds", registry.definitions.len());
+    commands.insert_resource(TileTextures {
+        handles: registry
+            .definitions
+            .iter()
+            .map(|definition| asset_server.load(definition.texture.clone()))
+            .collect(),
+    });
+    commands.insert_resource(registry.clone());
+    chunk_tiles.unload_all(&mut commands);
+}
+
+#[derive(Default)]
+pub struct TileLoader;
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum TileLoaderError {
+    #[error("could not read tile file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse tile file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("tile {0:?} is defined twice")]
+    DuplicateId(String),
+    #[error("no tile is defined with id {0:?}")]
+    UnknownId(String),
+    #[error("expected 1 to 255 corruption stages, found {0}")]
+    CorruptionStages(usize),
+}
+
+/// Parses the contents of a `.tiles.ron` file.
+pub fn tile_registry_from_ron(bytes: &[u8]) -> Result<TileRegistry, TileLoaderError> {
+    let file: TileFile = ron::de::from_bytes(bytes)?;
+    TileRegistry::new(file.tiles, &file.corruption_stages)
+}
+
+impl AssetLoader for TileLoader {
+    type Asset = TileRegistry;
+    type Settings = ();
+    type Error = TileLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            tile_registry_from_ron(&bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tiles.ron"]
+    }
+}
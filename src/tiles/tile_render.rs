@@ -1,17 +1,174 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bevy::utils::HashMap;
 
+use super::{
+    generate_chunk, ChunkGenerator, CorruptionState, CurrentTileSet, PotentiallyCorruptedTiles,
+    TileMap, TILE_SIZE,
+};
+use crate::player::Player;
+
 // Constants and resources related to rendering
+/// Chunks closer than this many pixels to the player are kept loaded.
 pub const SAFE_RADIUS: f32 = 360.0;
+/// Width and height of a chunk, in tiles.
+pub const CHUNK_SIZE: i32 = 16;
 
 #[derive(Resource, Default)]
 pub struct LoadedChunks {
+    /// The entity holding the tiles of each loaded chunk.
     pub chunks: HashMap<IVec2, Entity>,
-    pub corruption_tiles: HashMap<IVec2, Vec<(Entity, Transform)>>,
-    pub corruption_neighbors: HashMap<IVec2, Vec<Entity>>,
+    /// Corrupted tiles of every chunk generated so far, loaded or not, with
+    /// what lies under them. A chunk keeps its corruption here while it is unloaded.
+    pub corruption_tiles: HashMap<IVec2, HashMap<(i32, i32), CorruptionState>>,
+}
+
+impl LoadedChunks {
+    pub fn is_corrupted(&self, tile: (i32, i32)) -> bool {
+        self.corruption_tiles
+            .get(&chunk_of(IVec2::new(tile.0, tile.1)))
+            .is_some_and(|tiles| tiles.contains_key(&tile))
+    }
+
+    pub fn set_corrupted(&mut self, tile: (i32, i32), state: CorruptionState) {
+        self.corruption_tiles
+            .entry(chunk_of(IVec2::new(tile.0, tile.1)))
+            .or_default()
+            .insert(tile, state);
+    }
+
+    pub fn corruption_mut(&mut self, tile: (i32, i32)) -> Option<&mut CorruptionState> {
+        self.corruption_tiles
+            .get_mut(&chunk_of(IVec2::new(tile.0, tile.1)))?
+            .get_mut(&tile)
+    }
+
+    /// Forgets the corruption of `tile`, returning what it was.
+    pub fn clear_corrupted(&mut self, tile: (i32, i32)) -> Option<CorruptionState> {
+        self.corruption_tiles
+            .get_mut(&chunk_of(IVec2::new(tile.0, tile.1)))?
+            .remove(&tile)
+    }
+}
+
+/// The loaded chunks and the tile lookups kept in step with them.
+#[derive(SystemParam)]
+pub struct ChunkTiles<'w> {
+    pub loaded_chunks: ResMut<'w, LoadedChunks>,
+    pub tile_map: ResMut<'w, TileMap>,
+    pub potentially_corrupted_tiles: ResMut<'w, PotentiallyCorruptedTiles>,
+}
+
+impl ChunkTiles<'_> {
+    /// Unloads every loaded chunk, as `unload_chunk` does.
+    pub fn unload_all(&mut self, commands: &mut Commands) {
+        let chunks: Vec<IVec2> = self.loaded_chunks.chunks.keys().copied().collect();
+        for chunk in chunks {
+            unload_chunk(
+                commands,
+                chunk,
+                &mut self.loaded_chunks,
+                &mut self.tile_map,
+                &mut self.potentially_corrupted_tiles,
+            );
+        }
+    }
+}
+
+/// Textures of the `TileRegistry` in order, shared by every chunk's tilemap.
+#[derive(Resource)]
+pub struct TileTextures {
+    pub handles: Vec<Handle<Image>>,
+}
+
+/// The chunk a tile coordinate falls in.
+pub fn chunk_of(tile: IVec2) -> IVec2 {
+    tile.div_euclid(IVec2::splat(CHUNK_SIZE))
+}
+
+/// Pixels from `position` to the nearest point of `chunk`.
+fn distance_to_chunk(position: Vec2, chunk: IVec2) -> f32 {
+    let size = CHUNK_SIZE as f32 * TILE_SIZE;
+    // Tiles are centred on their coordinate, so chunks start half a tile early
+    let min = chunk.as_vec2() * size - TILE_SIZE / 2.0;
+    let nearest = position.clamp(min, min + size);
+    position.distance(nearest)
 }
 
 // Systems for rendering tiles
-pub fn setup_tiles(mut commands: Commands) {
+pub fn setup_tiles(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(LoadedChunks::default());
+    commands.insert_resource(CurrentTileSet(asset_server.load("tiles/default.tiles.ron")));
+}
+
+/// Despawns a loaded chunk and forgets its tiles. Its corruption stays in
+/// `LoadedChunks::corruption_tiles`.
+pub fn unload_chunk(
+    commands: &mut Commands,
+    chunk: IVec2,
+    loaded_chunks: &mut LoadedChunks,
+    tile_map: &mut TileMap,
+    potentially_corrupted_tiles: &mut PotentiallyCorruptedTiles,
+) {
+    let Some(chunk_entity) = loaded_chunks.chunks.remove(&chunk) else {
+        return;
+    };
+    commands.entity(chunk_entity).despawn_recursive();
+
+    let origin = chunk * CHUNK_SIZE;
+    for y in origin.y..origin.y + CHUNK_SIZE {
+        for x in origin.x..origin.x + CHUNK_SIZE {
+            tile_map.tiles.remove(&(x, y));
+            potentially_corrupted_tiles.remove((x, y));
+        }
+    }
+}
+
+/// Generates the chunks within `SAFE_RADIUS` of the player and despawns those
+/// that have fallen a chunk beyond it.
+pub fn stream_chunks(
+    mut commands: Commands,
+    generator: ChunkGenerator,
+    player_query: Query<&Transform, With<Player>>,
+    mut chunk_tiles: ChunkTiles,
+) {
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let position = player_transform.translation.truncate();
+    let chunk_pixels = CHUNK_SIZE as f32 * TILE_SIZE;
+
+    // Unload a chunk further out than it was loaded, so walking along a chunk
+    // border doesn't load and unload it every frame
+    let far_chunks: Vec<IVec2> = chunk_tiles
+        .loaded_chunks
+        .chunks
+        .keys()
+        .copied()
+        .filter(|chunk| distance_to_chunk(position, *chunk) > SAFE_RADIUS + chunk_pixels)
+        .collect();
+    for chunk in far_chunks {
+        unload_chunk(
+            &mut commands,
+            chunk,
+            &mut chunk_tiles.loaded_chunks,
+            &mut chunk_tiles.tile_map,
+            &mut chunk_tiles.potentially_corrupted_tiles,
+        );
+    }
+
+    let player_chunk = (position / chunk_pixels).floor().as_ivec2();
+    let reach = (SAFE_RADIUS / chunk_pixels).ceil() as i32 + 1;
+    for y in -reach..=reach {
+        for x in -reach..=reach {
+            let chunk = player_chunk + IVec2::new(x, y);
+            if chunk_tiles.loaded_chunks.chunks.contains_key(&chunk)
+                || distance_to_chunk(position, chunk) > SAFE_RADIUS
+            {
+                continue;
+            }
+
+            generate_chunk(&mut commands, &generator, chunk, &mut chunk_tiles);
+        }
+    }
 }
@@ -0,0 +1,98 @@
+// world_seed.rs
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+
+/// Keeps the random streams of different systems apart, so adding a random
+/// choice to one doesn't change what the others do.
+pub const STAGE_STREAM: u64 = 1;
+pub const CORRUPTION_STREAM: u64 = 2;
+
+#[derive(Debug, Error)]
+pub enum SeedError {
+    #[error("--seed needs a number after it")]
+    Missing,
+    #[error("invalid seed {0:?}, expected a whole number")]
+    Invalid(String),
+}
+
+/// The seed behind every random decision about the world. The same seed gives
+/// the same stage and the same corruption spread.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// Reads `--seed <number>` from the command line, or picks a random seed.
+    pub fn from_args(args: &[String]) -> Result<WorldSeed, SeedError> {
+        let Some(position) = args.iter().position(|arg| arg == "--seed") else {
+            return Ok(WorldSeed(rand::thread_rng().gen()));
+        };
+
+        let value = args.get(position + 1).ok_or(SeedError::Missing)?;
+        value
+            .parse()
+            .map(WorldSeed)
+            .map_err(|_| SeedError::Invalid(value.clone()))
+    }
+
+    /// A random number generator for one `stream` of the world.
+    pub fn rng(self, stream: u64) -> StdRng {
+        StdRng::seed_from_u64(self.0 ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    /// A random number generator for one `stream` at one place in the world,
+    /// independent of the order places are visited in.
+    pub fn rng_at(self, stream: u64, position: IVec2) -> StdRng {
+        let position = ((position.x as u32 as u64) << 32) | position.y as u32 as u64;
+        WorldSeed(self.0 ^ position.wrapping_mul(0xc2b2_ae3d_27d4_eb4f)).rng(stream)
+    }
+}
+
+/// Random choices made by `corruption_system`.
+#[derive(Resource)]
+pub struct CorruptionRng(pub StdRng);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_the_seed_from_the_arguments() {
+        assert_eq!(
+            WorldSeed::from_args(&args(&["game", "--seed", "42"])).unwrap(),
+            WorldSeed(42)
+        );
+        assert!(WorldSeed::from_args(&args(&["game"])).is_ok());
+        assert!(matches!(
+            WorldSeed::from_args(&args(&["game", "--seed"])),
+            Err(SeedError::Missing)
+        ));
+        assert!(matches!(
+            WorldSeed::from_args(&args(&["game", "--seed", "hymn"])),
+            Err(SeedError::Invalid(value)) if value == "hymn"
+        ));
+    }
+
+    #[test]
+    fn streams_repeat_for_the_same_seed() {
+        let draw = |mut rng: StdRng| (0..8).map(|_| rng.gen()).collect::<Vec<u64>>();
+
+        assert_eq!(
+            draw(WorldSeed(7).rng(CORRUPTION_STREAM)),
+            draw(WorldSeed(7).rng(CORRUPTION_STREAM))
+        );
+        assert_ne!(
+            draw(WorldSeed(7).rng(CORRUPTION_STREAM)),
+            draw(WorldSeed(7).rng(STAGE_STREAM))
+        );
+        assert_ne!(
+            draw(WorldSeed(7).rng(CORRUPTION_STREAM)),
+            draw(WorldSeed(8).rng(CORRUPTION_STREAM))
+        );
+    }
+}